@@ -0,0 +1,127 @@
+// std
+use std::collections::BTreeMap;
+// third-party
+use ark_bn254::Fr;
+use crate::default_hashes;
+use crate::poseidon::poseidon_hash_;
+use crate::merkle_tree_utils::{leaf_node, node_parent, node_sibling};
+
+/// Compute all the nodes of a merkle tree from its leaves (leaf index -> value), level by level
+///
+/// Returns the levels from the leaves (index 0) up to the root (index depth). Each level only contains
+/// the nodes that are not empty as (node index, value), sorted by node index.
+/// Note: leaf indexes must be < 2^depth
+pub fn build_levels(depth: usize, leaves: &BTreeMap<usize, Fr>) -> Vec<Vec<(usize, Fr)>> {
+
+    let level_hashes = default_hashes(depth);
+
+    let mut level: Vec<(usize, Fr)> = leaves
+        .iter()
+        .filter(|(_, value)| **value != level_hashes[0])
+        .map(|(leaf_index, value)| (leaf_node(depth, *leaf_index), *value))
+        .collect();
+    let mut levels = Vec::with_capacity(depth + 1);
+
+    for height in 0..depth {
+        let mut parents = Vec::with_capacity(level.len() / 2 + 1);
+        let mut nodes = level.iter().peekable();
+        while let Some((node, value)) = nodes.next() {
+            // Note: left child has an odd index, an empty sibling is not in the level
+            let (left, right) = if node & 1 == 1 {
+                match nodes.next_if(|(sibling, _)| *sibling == node + 1) {
+                    Some((_, sibling_value)) => (*value, *sibling_value),
+                    None => (*value, level_hashes[height]),
+                }
+            } else {
+                (level_hashes[height], *value)
+            };
+            // unwrap safe: the root (index 0) is never below depth
+            parents.push((node_parent(*node).unwrap(), poseidon_hash_(&[left, right])));
+        }
+        levels.push(level);
+        level = parents;
+    }
+    levels.push(level);
+
+    levels
+}
+
+/// Root of a merkle tree given its leaves (cf. build_levels)
+pub fn build_root(depth: usize, leaves: &BTreeMap<usize, Fr>) -> Fr {
+    build_levels(depth, leaves)[depth]
+        .first()
+        .map(|(_, root)| *root)
+        .unwrap_or_else(|| default_hashes(depth)[depth])
+}
+
+/// Merkle proof (cf. proof::MerkleProof) of a leaf given all the leaves of the tree (cf. build_levels)
+pub fn build_proof(depth: usize, leaves: &BTreeMap<usize, Fr>, leaf_index: usize) -> Vec<(i64, Fr)> {
+
+    let levels = build_levels(depth, leaves);
+    let level_hashes = default_hashes(depth);
+
+    let mut node = leaf_node(depth, leaf_index);
+    let mut path = Vec::with_capacity(depth);
+    for (height, level) in levels.iter().take(depth).enumerate() {
+        let sibling = node_sibling(node);
+        let value = level
+            .binary_search_by_key(&sibling, |(index, _)| *index)
+            .map(|position| level[position].1)
+            .unwrap_or(level_hashes[height]);
+        // Note: left child has an odd index (flag 0)
+        path.push((if node & 1 == 1 { 0 } else { 1 }, value));
+        // unwrap safe: the root (index 0) is never below depth
+        node = node_parent(node).unwrap();
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    #[test]
+    fn test_build_root() {
+        // cf. zerokit_ref (depth 3)
+        let mut leaves = BTreeMap::new();
+        assert_eq!(
+            build_root(3, &leaves),
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+        leaves.insert(0, Fr::from(2));
+        assert_eq!(
+            build_root(3, &leaves),
+            Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap()
+        );
+        leaves.insert(7, Fr::from(42));
+        assert_eq!(
+            build_root(3, &leaves),
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        // Empty leaves are ignored
+        leaves.insert(3, Fr::from(0));
+        assert_eq!(build_levels(3, &leaves)[0], vec![(7, Fr::from(2)), (14, Fr::from(42))]);
+    }
+
+    #[test]
+    fn test_build_proof() {
+        let leaves = BTreeMap::from([(0, Fr::from(2)), (7, Fr::from(42))]);
+        let root = build_root(3, &leaves);
+        for leaf_index in 0..8 {
+            let path = build_proof(3, &leaves, leaf_index);
+            assert_eq!(path.len(), 3);
+            let leaf = leaves.get(&leaf_index).copied().unwrap_or_default();
+            assert_eq!(crate::proof::proof_root(leaf, &path), root);
+        }
+    }
+
+    #[test]
+    fn test_build_levels_full_tree() {
+        let leaves: BTreeMap<usize, Fr> = (0..8).map(|i| (i, Fr::from(i as u64 + 1))).collect();
+        let levels = build_levels(3, &leaves);
+        assert_eq!(levels.iter().map(|level| level.len()).collect::<Vec<_>>(), vec![8, 4, 2, 1]);
+        assert_eq!(levels[1][0], (3, poseidon_hash_(&[Fr::from(1), Fr::from(2)])));
+        assert_eq!(levels[3][0].0, 0);
+    }
+}
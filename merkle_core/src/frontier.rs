@@ -0,0 +1,115 @@
+// third-party
+use ark_bn254::Fr;
+use crate::default_hashes;
+use crate::poseidon::poseidon_hash_;
+
+/// Right frontier of an append only merkle tree (as the eth2 deposit contract)
+///
+/// Only the last left node of each level is kept (depth + 1 nodes): an append is O(depth) hashes
+/// and the root can be computed without the other nodes of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frontier {
+    pub depth: usize,
+    /// Number of appended leaves (i.e. index of the next leaf)
+    pub next_index: u64,
+    /// For each level (from the leaves), the last node that is a left child
+    /// (the last one is the root once the tree is full)
+    pub branch: Vec<Fr>,
+}
+
+impl Frontier {
+
+    pub fn new(depth: usize) -> Self {
+        Frontier { depth, next_index: 0, branch: vec![Fr::default(); depth + 1] }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        1u64 << self.depth
+    }
+
+    /// Append a leaf. Returns its leaf index
+    pub fn append(&mut self, leaf: Fr) -> Result<u64, String> {
+        let leaf_index = self.next_index;
+        if leaf_index >= self.capacity() {
+            return Err(format!("Merkle tree of depth {} is full", self.depth));
+        }
+
+        self.next_index += 1;
+        let mut node = leaf;
+        let mut size = self.next_index;
+        // Note: the last leaf completes the root (size is 1 at level depth)
+        for height in 0..=self.depth {
+            // Note: the new node is a left child if size is odd at this level
+            if size & 1 == 1 {
+                self.branch[height] = node;
+                break;
+            }
+            node = poseidon_hash_(&[self.branch[height], node]);
+            size >>= 1;
+        }
+
+        Ok(leaf_index)
+    }
+
+    pub fn root(&self) -> Fr {
+        if self.next_index == self.capacity() {
+            return self.branch[self.depth];
+        }
+
+        let zero_hashes = default_hashes(self.depth);
+        let mut node = Fr::default();
+        let mut size = self.next_index;
+        for height in 0..self.depth {
+            node = if size & 1 == 1 {
+                poseidon_hash_(&[self.branch[height], node])
+            } else {
+                poseidon_hash_(&[node, zero_hashes[height]])
+            };
+            size >>= 1;
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+    use super::*;
+    use crate::build::build_root;
+
+    #[test]
+    fn test_frontier_root() {
+        let mut frontier = Frontier::new(3);
+        assert_eq!(frontier.root(), default_hashes(3)[3]);
+
+        assert_eq!(frontier.append(Fr::from(2)).unwrap(), 0);
+        // Same root as zerokit_ref (leaf 0 = 2)
+        assert_eq!(
+            frontier.root(),
+            Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap()
+        );
+
+        // Full tree: same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        for leaf in [0, 0, 0, 0, 0, 0, 42] {
+            frontier.append(Fr::from(leaf)).unwrap();
+        }
+        assert_eq!(
+            frontier.root(),
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_frontier_build_root() {
+        let mut frontier = Frontier::new(4);
+        let mut leaves = BTreeMap::new();
+        for i in 0..16u64 {
+            let leaf = Fr::from(i * 7 + 1);
+            assert_eq!(frontier.append(leaf).unwrap(), i);
+            leaves.insert(i as usize, leaf);
+            assert_eq!(frontier.root(), build_root(4, &leaves));
+        }
+        assert!(frontier.append(Fr::from(1)).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+pub mod build;
+pub mod frontier;
+pub mod merkle_tree_utils;
+pub mod poseidon;
+pub mod proof;
+pub mod snapshot;
+
+use ark_bn254::Fr;
+use crate::poseidon::poseidon_hash_;
+
+/// Hash of an empty node for each level of the tree, from the leaves (index 0) up to the root (index depth)
+pub fn default_hashes(depth: usize) -> Vec<Fr> {
+    let mut level_hashes = Vec::with_capacity(depth + 1);
+    level_hashes.push(Fr::default()); // set the initial leaf value
+    // Compute hash from the initial leaf value up to the root node
+    (0..depth).for_each(|level_index| {
+        level_hashes.push(poseidon_hash_(&[level_hashes[level_index]; 2]))
+    });
+    level_hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    #[test]
+    fn test_default_hashes() {
+        let hashes = default_hashes(3);
+        assert_eq!(hashes.len(), 4);
+        assert_eq!(hashes[0], Fr::from(0));
+        assert_eq!(
+            hashes[1],
+            Fr::from_str("14744269619966411208579211824598458697587494354926760081771325075741142829156").unwrap()
+        );
+        assert_eq!(
+            hashes[2],
+            Fr::from_str("7423237065226347324353380772367382631490014989348495481811164164159255474657").unwrap()
+        );
+        // Root of an empty tree of depth 3 (cf. zerokit_ref)
+        assert_eq!(
+            hashes[3],
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+pub fn node_parent(index: usize) -> Option<usize> {
+    if index == 0 {
+        None
+    } else {
+        // Note: same as ((index + 1) >> 1) - 1 but cannot overflow
+        Some((index - 1) >> 1)
+    }
+}
+
+/// Index of the left child (the right child is first_child + 1)
+///
+/// Note: overflows if index > (usize::MAX - 2) / 2
+pub fn first_child(index: usize) -> usize {
+    (index << 1) + 1
+}
+
+pub fn node_sibling(index: usize) -> usize {
+    // Note: left child has an odd index, right child has an even index
+    if index & 1 == 1 {
+        index + 1
+    } else {
+        index - 1
+    }
+}
+
+/// Indexes of the nodes required to compute the root from the given leaf nodes (bottom to top,
+/// ascending within a level). Siblings that can be computed from the leaves are not included.
+pub fn multiproof_indexes(leaf_nodes: &[usize]) -> Vec<usize> {
+
+    let mut level: BTreeSet<usize> = leaf_nodes.iter().copied().collect();
+    let mut indexes = Vec::new();
+
+    while !level.is_empty() && !level.contains(&0) {
+        let mut parents = BTreeSet::new();
+        for &node in level.iter() {
+            let sibling = node_sibling(node);
+            if !level.contains(&sibling) {
+                indexes.push(sibling);
+            }
+            // unwrap safe: root node (index 0) is never in level
+            parents.insert(node_parent(node).unwrap());
+        }
+        level = parents;
+    }
+
+    indexes
+}
+
+/// Index (in the merkle tree table) of a leaf
+pub fn leaf_node(depth: usize, leaf_index: usize) -> usize {
+    (1 << depth) + leaf_index - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    // Largest node index whose 2 children are in the usize range
+    const MAX_PARENT: usize = (usize::MAX - 2) / 2;
+
+    #[test]
+    fn test_small_tree() {
+        // depth 2: 0 -> (1, 2), 1 -> (3, 4), 2 -> (5, 6)
+        assert_eq!(node_parent(0), None);
+        assert_eq!((1..7).map(|i| node_parent(i).unwrap()).collect::<Vec<_>>(), vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!((0..3).map(first_child).collect::<Vec<_>>(), vec![1, 3, 5]);
+        assert_eq!((1..7).map(node_sibling).collect::<Vec<_>>(), vec![2, 1, 4, 3, 6, 5]);
+        assert_eq!((0..4).map(|i| leaf_node(2, i)).collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        assert_eq!(node_parent(usize::MAX), Some(MAX_PARENT + 1));
+    }
+
+    #[test]
+    fn test_multiproof_indexes_single_leaf() {
+        // Leaf 5 of a depth 3 tree -> node 12: siblings 11, 6, 1
+        assert_eq!(multiproof_indexes(&[leaf_node(3, 5)]), vec![11, 6, 1]);
+        assert!(multiproof_indexes(&[]).is_empty());
+        assert!(multiproof_indexes(&[0]).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn test_parent_of_children(index in 0..=MAX_PARENT) {
+            let child = first_child(index);
+            prop_assert_eq!(child & 1, 1);
+            prop_assert_eq!(node_parent(child), Some(index));
+            prop_assert_eq!(node_parent(child + 1), Some(index));
+            prop_assert_eq!(node_sibling(child), child + 1);
+        }
+
+        #[test]
+        fn test_children_of_parent(index in 1..=usize::MAX) {
+            let parent = node_parent(index).unwrap();
+            prop_assert!(parent < index);
+            prop_assert!(index == first_child(parent) || index == first_child(parent) + 1);
+        }
+
+        #[test]
+        fn test_sibling_involution(index in 1..usize::MAX) {
+            let sibling = node_sibling(index);
+            prop_assert_ne!(sibling, index);
+            prop_assert_eq!(node_sibling(sibling), index);
+            prop_assert_eq!(node_parent(sibling), node_parent(index));
+        }
+
+        #[test]
+        fn test_leaf_node_depth(depth in 1usize..63, leaf_index in any::<u64>()) {
+            let leaf_index = (leaf_index as usize) & ((1 << depth) - 1);
+            // A leaf is depth levels below the root
+            let mut node = leaf_node(depth, leaf_index);
+            for _ in 0..depth {
+                node = node_parent(node).unwrap();
+            }
+            prop_assert_eq!(node, 0);
+        }
+
+        #[test]
+        fn test_multiproof_indexes_size(leaves in prop::collection::btree_set(0usize..256, 1..32)) {
+            let leaf_nodes: Vec<usize> = leaves.iter().map(|leaf_index| leaf_node(8, *leaf_index)).collect();
+            let indexes = multiproof_indexes(&leaf_nodes);
+            // At most 1 sibling per level per leaf, never one of the leaves
+            prop_assert!(indexes.len() <= 8 * leaves.len());
+            prop_assert!(indexes.iter().all(|index| !leaf_nodes.contains(index)));
+            let unique: BTreeSet<usize> = indexes.iter().copied().collect();
+            prop_assert_eq!(unique.len(), indexes.len());
+        }
+    }
+}
@@ -270,6 +270,11 @@ pub fn poseidon_hash_(input: &[Fr]) -> Fr {
         .expect("hash with fixed input size can't fail")
 }
 
+/// Same as poseidon_hash_ but for a variable input size (1 up to 8 inputs, cf. ROUND_PARAMS)
+pub fn poseidon_hash(input: &[Fr]) -> Result<Fr, String> {
+    POSEIDON.hash(input)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RoundParameters<F: PrimeField> {
     pub t: usize,
@@ -406,4 +411,79 @@ where
     fn default() -> Self {
         Self::from(&[])
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use proptest::prelude::*;
+    use super::*;
+
+    // circomlib reference values: poseidon([1, 2, ..., n]) for n = 1 up to 8 (every arity in ROUND_PARAMS)
+    const REFERENCE_HASHES: [&str; 8] = [
+        "18586133768512220936620570745912940619677854269274689475585506675881198879027",
+        "7853200120776062878684798364095072458815029376092732009249414926327459813530",
+        "6542985608222806190361240322586112750744169038454362455181422643027100751666",
+        "18821383157269793795438455681495246036402687001665670618754263018637548127333",
+        "6183221330272524995739186171720101788151706631170188140075976616310159254464",
+        "20400040500897583745843009878988256314335038853985262692600694741116813247201",
+        "12748163991115452309045839028154629052133952896122405799815156419278439301912",
+        "18604317144381847857886385684060986177838410221561136253933256952257712543953",
+    ];
+
+    #[test]
+    fn test_poseidon_hash() {
+        // circomlib reference value
+        assert_eq!(
+            poseidon_hash_(&[Fr::from(1), Fr::from(2)]),
+            Fr::from_str("7853200120776062878684798364095072458815029376092732009249414926327459813530").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_poseidon_reference_vectors() {
+        assert_eq!(REFERENCE_HASHES.len(), ROUND_PARAMS.len());
+        for (n, expected) in (1..).zip(REFERENCE_HASHES) {
+            let input: Vec<Fr> = (1..=n).map(Fr::from).collect();
+            assert_eq!(poseidon_hash(&input), Ok(Fr::from_str(expected).unwrap()), "{n} inputs");
+        }
+    }
+
+    #[test]
+    fn test_poseidon_unsupported_length() {
+        assert!(poseidon_hash(&[]).is_err());
+        assert!(poseidon_hash(&[Fr::from(1); 9]).is_err());
+        assert!(poseidon_hash(&[Fr::from(1); 16]).is_err());
+        // No round parameters at all
+        assert!(Poseidon::<Fr>::default().hash(&[Fr::from(1)]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "hash with fixed input size can't fail")]
+    fn test_poseidon_hash_unsupported_length() {
+        poseidon_hash_(&[Fr::from(1); 9]);
+    }
+
+    fn fr() -> impl Strategy<Value = Fr> {
+        any::<[u8; 32]>().prop_map(|bytes| Fr::from_le_bytes_mod_order(&bytes))
+    }
+
+    proptest! {
+        #[test]
+        fn test_poseidon_hash_variants_match(input in prop::collection::vec(fr(), 1..=8)) {
+            prop_assert_eq!(poseidon_hash(&input), Ok(poseidon_hash_(&input)));
+        }
+
+        #[test]
+        fn test_poseidon_hash_is_order_sensitive(a in fr(), b in fr()) {
+            prop_assume!(a != b);
+            prop_assert_ne!(poseidon_hash_(&[a, b]), poseidon_hash_(&[b, a]));
+        }
+
+        #[test]
+        fn test_poseidon_hash_no_padding_collision(a in fr()) {
+            // Inputs of different lengths use different parameters
+            prop_assert_ne!(poseidon_hash_(&[a]), poseidon_hash_(&[a, Fr::from(0)]));
+        }
+    }
+}
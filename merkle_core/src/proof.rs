@@ -0,0 +1,167 @@
+// std
+use std::collections::BTreeMap;
+// third-party
+use ark_bn254::Fr;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use crate::poseidon::poseidon_hash_;
+use crate::merkle_tree_utils::{node_parent, node_sibling};
+
+/// A merkle proof: (0 if left child or 1 if right child, sibling value) from the leaf up to the root
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub path: Vec<(i64, Fr)>,
+}
+
+impl MerkleProof {
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        Ok(MerkleProof { path: Vec::<(i64, Fr)>::deserialize_compressed(bytes)? })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.path.serialize_compressed(&mut buffer).expect("Serialization failed");
+        buffer
+    }
+
+    pub fn compute_root(&self, leaf: Fr) -> Fr {
+        proof_root(leaf, &self.path)
+    }
+
+    pub fn verify(&self, leaf: Fr, root: Fr) -> bool {
+        self.compute_root(leaf) == root
+    }
+}
+
+/// Compute the root from a leaf and a proof path (cf. MerkleProof)
+pub fn proof_root(leaf: Fr, path: &[(i64, Fr)]) -> Fr {
+    path
+        .iter()
+        .fold(leaf, |node, (left_or_right, sibling)| {
+            match left_or_right {
+                // node is a left child
+                0 => poseidon_hash_(&[node, *sibling]),
+                // node is a right child
+                _ => poseidon_hash_(&[*sibling, node]),
+            }
+        })
+}
+
+/// A merkle proof for multiple leaves: (node index, node value) for the nodes that cannot be computed
+/// from the leaves (cf. multiproof_indexes)
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MultiProof {
+    pub nodes: Vec<(i64, Fr)>,
+}
+
+impl MultiProof {
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        Ok(MultiProof { nodes: Vec::<(i64, Fr)>::deserialize_compressed(bytes)? })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.nodes.serialize_compressed(&mut buffer).expect("Serialization failed");
+        buffer
+    }
+
+    /// Compute the root from the leaves (leaf node index -> leaf value). Returns None if the proof layout
+    /// does not match the leaves
+    pub fn compute_root(&self, leaf_nodes: BTreeMap<usize, Fr>) -> Option<Fr> {
+
+        let mut level = leaf_nodes;
+        let mut proof_iter = self.nodes.iter();
+
+        // Siblings are consumed in the same order as multiproof_indexes (bottom to top, ascending)
+        while !level.is_empty() && !level.contains_key(&0) {
+            let mut parents = BTreeMap::new();
+            for (&node, &value) in level.iter() {
+                // unwrap safe: root node (index 0) is never in level
+                let parent = node_parent(node).unwrap();
+                if parents.contains_key(&parent) {
+                    // Already computed from the left sibling
+                    continue;
+                }
+
+                let sibling = node_sibling(node);
+                let sibling_value = match level.get(&sibling) {
+                    Some(sibling_value) => *sibling_value,
+                    None => {
+                        let (index, sibling_value) = proof_iter.next()?;
+                        if *index != sibling as i64 {
+                            return None;
+                        }
+                        *sibling_value
+                    }
+                };
+
+                let hash = match node & 1 {
+                    1 => poseidon_hash_(&[value, sibling_value]),
+                    _ => poseidon_hash_(&[sibling_value, value]),
+                };
+                parents.insert(parent, hash);
+            }
+            level = parents;
+        }
+
+        // All the proof nodes must have been used
+        if proof_iter.next().is_some() {
+            return None;
+        }
+
+        level.get(&0).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree_utils::{leaf_node, multiproof_indexes};
+
+    // All the nodes of a merkle tree of depth 3 with leaf i = i * i
+    fn full_tree() -> Vec<Fr> {
+        let mut nodes = vec![Fr::from(0); 15];
+        for leaf_index in 0..8 {
+            nodes[leaf_node(3, leaf_index)] = Fr::from((leaf_index * leaf_index) as u64);
+        }
+        for index in (0..7).rev() {
+            nodes[index] = poseidon_hash_(&[nodes[2 * index + 1], nodes[2 * index + 2]]);
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let nodes = full_tree();
+        // Leaf 5 -> node 12 (right child), parent 5 (left child), parent 2 (right child)
+        let proof = MerkleProof { path: vec![(1, nodes[11]), (0, nodes[6]), (1, nodes[1])] };
+        assert!(proof.verify(Fr::from(25), nodes[0]));
+        assert!(!proof.verify(Fr::from(24), nodes[0]));
+        assert_eq!(MerkleProof::from_bytes(&proof.to_bytes()).unwrap(), proof);
+    }
+
+    #[test]
+    fn test_multiproof() {
+        let nodes = full_tree();
+        for leaves in [vec![0], vec![7], vec![0, 1, 7], vec![3, 4], (0..8).collect()] {
+            let leaf_nodes: Vec<usize> = leaves.iter().map(|leaf_index| leaf_node(3, *leaf_index)).collect();
+            let proof = MultiProof {
+                nodes: multiproof_indexes(&leaf_nodes)
+                    .into_iter()
+                    .map(|index| (index as i64, nodes[index]))
+                    .collect()
+            };
+            let leaf_values: BTreeMap<usize, Fr> = leaf_nodes.iter().map(|index| (*index, nodes[*index])).collect();
+            assert_eq!(proof.compute_root(leaf_values.clone()), Some(nodes[0]));
+
+            // Wrong leaf value
+            let mut wrong_values = leaf_values.clone();
+            *wrong_values.values_mut().next().unwrap() += Fr::from(1);
+            assert_ne!(proof.compute_root(wrong_values), Some(nodes[0]));
+        }
+
+        // Leaves 0 & 1 are siblings, so only 1 node per level is required for them
+        assert_eq!(multiproof_indexes(&[leaf_node(3, 7), leaf_node(3, 0), leaf_node(3, 1)]), vec![13, 4, 5]);
+    }
+}
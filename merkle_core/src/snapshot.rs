@@ -0,0 +1,134 @@
+// third-party
+use ark_bn254::Fr;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"PGMT";
+pub const SNAPSHOT_VERSION: u8 = 1;
+/// Poseidon over BN254 with the circom parameters (cf. poseidon::ROUND_PARAMS)
+pub const HASH_POSEIDON_BN254: u8 = 1;
+
+/// A compact merkle tree snapshot: only the leaves that are not empty are stored
+///
+/// Layout: magic (4 bytes) | version (u8) | hash id (u8) | depth (u8) | empty leaf (Fr) | root (Fr)
+/// | leaf count (u64) | (leaf index (u64), leaf value (Fr)) * leaf count
+/// Integers are little endian, Fr are ark serialized (compressed, 32 bytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub hash_id: u8,
+    pub depth: u8,
+    pub empty_leaf: Fr,
+    pub root: Fr,
+    pub leaves: Vec<(u64, Fr)>,
+}
+
+impl Snapshot {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(4 + 3 + 32 * 2 + 8 + self.leaves.len() * (8 + 32));
+        buffer.extend_from_slice(SNAPSHOT_MAGIC);
+        buffer.extend_from_slice(&[SNAPSHOT_VERSION, self.hash_id, self.depth]);
+        self.empty_leaf.serialize_compressed(&mut buffer).expect("Serialization failed");
+        self.root.serialize_compressed(&mut buffer).expect("Serialization failed");
+        buffer.extend_from_slice(&(self.leaves.len() as u64).to_le_bytes());
+        for (index, value) in self.leaves.iter() {
+            buffer.extend_from_slice(&index.to_le_bytes());
+            value.serialize_compressed(&mut buffer).expect("Serialization failed");
+        }
+        buffer
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, String> {
+
+        let header = take(&mut bytes, 7)?;
+        if &header[0..4] != SNAPSHOT_MAGIC {
+            return Err("Not a merkle tree snapshot (invalid magic)".to_string());
+        }
+        if header[4] != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version: {}", header[4]));
+        }
+        let hash_id = header[5];
+        let depth = header[6];
+
+        let empty_leaf = take_fr(&mut bytes)?;
+        let root = take_fr(&mut bytes)?;
+        let count = take_u64(&mut bytes)?;
+        // Note: check the size before allocating (count comes from the input)
+        if (bytes.len() as u64) != count.saturating_mul(8 + 32) {
+            return Err(format!("Invalid snapshot size for {} leaves", count));
+        }
+
+        let mut leaves = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let index = take_u64(&mut bytes)?;
+            leaves.push((index, take_fr(&mut bytes)?));
+        }
+
+        Ok(Snapshot { hash_id, depth, empty_leaf, root, leaves })
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err("Truncated snapshot".to_string());
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, String> {
+    let head = take(bytes, 8)?;
+    Ok(u64::from_le_bytes(head.try_into().expect("8 bytes")))
+}
+
+fn take_fr(bytes: &mut &[u8]) -> Result<Fr, String> {
+    let head = take(bytes, 32)?;
+    Fr::deserialize_compressed(head).map_err(|e| format!("Invalid field element in snapshot: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            hash_id: HASH_POSEIDON_BN254,
+            depth: 3,
+            empty_leaf: Fr::from(0),
+            root: Fr::from(1234),
+            leaves: vec![(0, Fr::from(2)), (7, Fr::from(42))],
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(bytes.len(), 7 + 64 + 8 + 2 * 40);
+        assert_eq!(&bytes[0..4], b"PGMT");
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+
+        let empty = Snapshot { leaves: vec![], ..snapshot };
+        assert_eq!(Snapshot::from_bytes(&empty.to_bytes()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_snapshot_invalid() {
+        let bytes = snapshot().to_bytes();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Snapshot::from_bytes(&bytes[..5]).is_err());
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(Snapshot::from_bytes(&wrong_magic).unwrap_err().contains("magic"));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 2;
+        assert!(Snapshot::from_bytes(&wrong_version).unwrap_err().contains("version"));
+
+        // Leaf count larger than the content
+        let mut wrong_count = bytes.clone();
+        wrong_count[71..79].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Snapshot::from_bytes(&wrong_count).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+// std
+use std::collections::{BTreeMap, BTreeSet};
+// third-party
+use ark_bn254::Fr;
+use merkle_core::{build::build_levels, default_hashes};
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::merkle_tree::{mtree_leaf_node, mtree_leaves};
+use crate::registry::{mtree_check_depth, mtree_lock, mtree_registered_depth, mtree_table, mtree_touch};
+
+/// Replace the content of the merkle tree: all the nodes are computed in memory then written with 1 query per level
+///
+/// Returns the new root
+pub(crate) fn mtree_build(tree: &str, depth: i16, leaves: Vec<(i64, Fr)>) -> SpiResult<PgFr> {
+    let _guard = MtreeWriteGuard::new();
+    mtree_check_depth(depth.into());
+    let table = mtree_table(tree, Some(depth))?;
+    mtree_lock(tree)?;
+
+    // Note: if a leaf index is given multiple times, the last value is used (as in pgfr_mtree_set_leaves)
+    let mut leaf_values = BTreeMap::new();
+    for (leaf_index, value) in leaves {
+        // Check the leaf index range
+        mtree_leaf_node(depth, leaf_index);
+        leaf_values.insert(leaf_index as usize, value);
+    }
+
+    let depth_ = depth as usize;
+    let levels = build_levels(depth_, &leaf_values);
+    let level_hashes = default_hashes(depth_);
+
+    // Change log (registered trees only): the leaves that were not empty or that are not empty anymore
+    let changes = if mtree_registered_depth(tree)?.is_some() {
+        let old_leaves: BTreeMap<usize, Fr> = mtree_leaves(&table, depth)?
+            .into_iter()
+            .map(|(leaf_index, value)| (leaf_index as usize, value))
+            .collect();
+        old_leaves
+            .keys()
+            .chain(leaf_values.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|leaf_index| (
+                *leaf_index as i64,
+                old_leaves.get(leaf_index).copied().unwrap_or_default(),
+                leaf_values.get(leaf_index).copied().unwrap_or_default(),
+            ))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Spi::run(&format!("TRUNCATE {table}"))?;
+    Spi::run_with_args("DELETE FROM pgfr_mtree_preimage WHERE tree = $1", &[tree.into()])?;
+
+    // Nodes that are not computed are empty nodes
+    let query = format!(r#"
+        INSERT INTO {table} (index_in_mtree, value)
+        SELECT i, coalesce(t.value, $1)
+        FROM generate_series($2, $3) AS i
+        LEFT JOIN UNNEST($4::bigint[], $5::pgfr[]) AS t(index_in_mtree, value)
+            ON t.index_in_mtree = i
+    "#);
+
+    for (height, nodes) in levels.iter().enumerate() {
+        let level = depth_ - height;
+        let level_start_index = (1i64 << level) - 1;
+        let level_end_index = (1i64 << (level + 1)) - 2;
+        let (indexes, values): (Vec<i64>, Vec<PgFr>) = nodes
+            .iter()
+            .map(|(index, value)| (*index as i64, PgFr(*value)))
+            .unzip();
+
+        Spi::run_with_args(
+            &query,
+            &[
+                PgFr(level_hashes[height]).into(),
+                level_start_index.into(),
+                level_end_index.into(),
+                indexes.into(),
+                values.into(),
+            ]
+        )?;
+    }
+
+    mtree_touch(tree, changes)?;
+
+    let root = levels[depth_]
+        .first()
+        .map(|(_, root)| *root)
+        .unwrap_or(level_hashes[depth_]);
+    Ok(PgFr(root))
+}
+
+/// Build the merkle tree from the values of the leaves 0..n (the previous content of the tree is removed)
+///
+/// Returns the new root
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_mtree_build(depth: i16, leaf_values: Vec<PgFr>, tree: default!(&str, "'pgfr_mtree'")) -> Result<PgFr, pgrx::spi::Error> {
+    let leaves = leaf_values
+        .into_iter()
+        .enumerate()
+        .map(|(leaf_index, value)| (leaf_index as i64, value.0))
+        .collect();
+    mtree_build(tree, depth, leaves)
+}
+
+/// Build the merkle tree from a query returning (leaf_index bigint, value pgfr) rows
+/// (the previous content of the tree is removed)
+///
+/// e.g. SELECT pgfr_mtree_build_from(20::smallint, 'SELECT leaf_index, commitment FROM members')
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_mtree_build_from(depth: i16, query: &str, tree: default!(&str, "'pgfr_mtree'")) -> Result<PgFr, pgrx::spi::Error> {
+
+    let leaves = Spi::connect(|client| {
+        let mut leaves = Vec::new();
+        for row in client.select(query, None, &[])? {
+            let Some(leaf_index) = row.get::<i64>(1)? else {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_NULL_VALUE_NOT_ALLOWED,
+                    "pgfr_mtree_build_from: leaf index cannot be NULL"
+                );
+            };
+            // Note: a NULL value is an empty leaf (as in pgfr_mtree_attach)
+            let value = row.get::<PgFr>(2)?.map(|value| value.0).unwrap_or_default();
+            leaves.push((leaf_index, value));
+        }
+        Ok::<_, pgrx::spi::Error>(leaves)
+    })?;
+
+    mtree_build(tree, depth, leaves)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use super::*;
+    use crate::registry::DEFAULT_TREE;
+
+    #[pg_test]
+    fn test_mtree_build() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 5, '5');
+            "
+        ).unwrap();
+
+        // Same values as zerokit_ref (leaf 5 is reset)
+        let values = ["2", "0", "0", "0", "0", "0", "0", "42"].map(|v| PgFr(Fr::from_str(v).unwrap()));
+        let root = pgfr_mtree_build(3, values.to_vec(), DEFAULT_TREE).unwrap();
+        let expected_root = Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap();
+        assert_eq!(root.0, expected_root);
+
+        // Same tree as with set_leaf
+        let count = Spi::get_one::<i64>("SELECT count(*) FROM pgfr_mtree;").unwrap().unwrap();
+        assert_eq!(count, 15);
+        let stored_root = Spi::get_one::<PgFr>("SELECT value FROM pgfr_mtree WHERE index_in_mtree = 0;").unwrap().unwrap();
+        assert_eq!(stored_root.0, expected_root);
+        let proof = Spi::get_one::<Vec<u8>>("SELECT pgfr_mtree_get_proof(3::smallint, 7);").unwrap().unwrap();
+        let proof = merkle_core::proof::MerkleProof::from_bytes(&proof).unwrap();
+        assert!(proof.verify(Fr::from(42), expected_root));
+    }
+
+    #[pg_test]
+    fn test_mtree_build_from() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            CREATE TABLE members (id serial, commitment pgfr, leaf_index bigint);
+            INSERT INTO members (commitment, leaf_index) VALUES ('42', 7), ('2', 0), (NULL, 3);
+            "
+        ).unwrap();
+
+        let root = pgfr_mtree_build_from(3, "SELECT leaf_index, commitment FROM members", DEFAULT_TREE).unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "out of range")]
+    fn test_mtree_build_out_of_range() {
+        let _res = Spi::run("CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);").unwrap();
+        pgfr_mtree_build_from(3, "SELECT 8::bigint, '1'::pgfr", DEFAULT_TREE).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "merkle tree depth must be between 1 and 32, received 40")]
+    fn test_mtree_build_depth_out_of_range() {
+        let _res = Spi::run("CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);").unwrap();
+        pgfr_mtree_build(40, vec![PgFr(Fr::from(1))], DEFAULT_TREE).unwrap();
+    }
+}
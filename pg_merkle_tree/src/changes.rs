@@ -0,0 +1,185 @@
+// third-party
+use ark_bn254::Fr;
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+
+// Leaf changes of the trees created by pgfr_mtree_create (in order: root_seq, seq)
+// Note: all the changes of a modification (e.g. pgfr_mtree_set_leaves) have the same root_seq (cf. mtree_touch)
+//       and the same (resulting) root
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_mtree_changes (
+    seq bigserial PRIMARY KEY,
+    tree_id integer NOT NULL REFERENCES pgfr_mtree_registry (tree_id) ON DELETE CASCADE,
+    root_seq bigint NOT NULL,
+    leaf_index bigint NOT NULL,
+    old_value pgfr NOT NULL,
+    new_value pgfr NOT NULL,
+    root pgfr NOT NULL,
+    txid bigint NOT NULL DEFAULT txid_current(),
+    changed_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX pgfr_mtree_changes_tree_index ON pgfr_mtree_changes (tree_id, root_seq);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_mtree_changes', '');
+"#,
+    name = "create_mtree_changes",
+    requires = ["create_mtree_registry"]
+);
+
+/// A leaf change: (leaf_index, old_value, new_value)
+pub(crate) type LeafChange = (i64, Fr, Fr);
+
+/// Append the leaf changes of a modification of a tree (the unchanged leaves are skipped)
+pub(crate) fn mtree_log_changes(tree_id: i32, root_seq: i64, root: Fr, changes: Vec<LeafChange>) -> SpiResult<()> {
+
+    let (leaf_indexes, (old_values, new_values)): (Vec<i64>, (Vec<PgFr>, Vec<PgFr>)) = changes
+        .into_iter()
+        .filter(|(_, old_value, new_value)| old_value != new_value)
+        .map(|(leaf_index, old_value, new_value)| (leaf_index, (PgFr(old_value), PgFr(new_value))))
+        .unzip();
+
+    if leaf_indexes.is_empty() {
+        return Ok(());
+    }
+
+    let query = r#"
+        INSERT INTO pgfr_mtree_changes (tree_id, root_seq, leaf_index, old_value, new_value, root)
+        SELECT $1, $2, t.leaf_index, t.old_value, t.new_value, $3
+        FROM UNNEST($4::bigint[], $5::pgfr[], $6::pgfr[]) AS t(leaf_index, old_value, new_value)
+        ORDER BY t.leaf_index
+    "#;
+
+    Spi::run_with_args(
+        query,
+        &[
+            tree_id.into(),
+            root_seq.into(),
+            PgFr(root).into(),
+            leaf_indexes.into(),
+            old_values.into(),
+            new_values.into(),
+        ]
+    )
+}
+
+/// Leaf changes of a registered tree after the given root sequence, in order
+///
+/// e.g. to keep a local copy of a tree in sync: apply the changes then store the last root_seq for the next call
+///
+/// Note: root_seq (not seq) is the cursor. seq is assigned when a change is logged so a change can be committed
+///       after a change with a higher seq. A modification holds the tree lock (cf. mtree_lock) from its first read
+///       to its commit, so the root sequences (cf. mtree_touch) of a tree are committed in order and a root sequence
+///       is only visible once all the previous ones are.
+/// Note: only the trees created with pgfr_mtree_create are logged (fails for another tree), and only the leaf
+///       modifications made by the extension functions: pgfr_mtree_repair rewrites the internal nodes from the
+///       leaves (a root sequence without change) and the writes with pg_merkle_tree.allow_direct_write are not logged
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_changes_since(root_seq: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<
+    TableIterator<'static, (
+        name!(seq, i64),
+        name!(root_seq, i64),
+        name!(leaf_index, i64),
+        name!(old_value, PgFr),
+        name!(new_value, PgFr),
+        name!(root, PgFr),
+        name!(txid, i64),
+    )>,
+    pgrx::spi::Error
+> {
+    let tree_id = Spi::get_one_with_args::<i32>(
+        "SELECT (SELECT tree_id FROM pgfr_mtree_registry WHERE name = $1)",
+        &[tree.into()]
+    )?;
+    let Some(tree_id) = tree_id else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("merkle tree {tree} is not registered, its changes are not logged (cf. pgfr_mtree_create)")
+        );
+    };
+
+    let query = r#"
+        SELECT seq, root_seq, leaf_index, old_value, new_value, root, txid
+        FROM pgfr_mtree_changes
+        WHERE tree_id = $1 AND root_seq > $2
+        ORDER BY root_seq, seq
+    "#;
+
+    let changes = Spi::connect(|client| {
+        let mut changes = Vec::new();
+        for row in client.select(query, None, &[tree_id.into(), root_seq.into()])? {
+            changes.push((
+                row.get::<i64>(1)?.expect("seq is not null"),
+                row.get::<i64>(2)?.expect("root_seq is not null"),
+                row.get::<i64>(3)?.expect("leaf_index is not null"),
+                row.get::<PgFr>(4)?.expect("old_value is not null"),
+                row.get::<PgFr>(5)?.expect("new_value is not null"),
+                row.get::<PgFr>(6)?.expect("root is not null"),
+                row.get::<i64>(7)?.expect("txid is not null"),
+            ));
+        }
+        Ok::<_, pgrx::spi::Error>(changes)
+    })?;
+
+    Ok(TableIterator::new(changes))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use super::*;
+
+    #[pg_test]
+    fn test_mtree_changes_since() {
+        let _res = Spi::run("
+            SELECT pgfr_mtree_create('members_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2', 'members_tree');
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[7, 0], ARRAY['42', '2']::pgfr[], 'members_tree');
+            SELECT pgfr_mtree_create('other_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 1, '1', 'other_tree');
+            "
+        ).unwrap();
+
+        let changes: Vec<_> = pgfr_mtree_changes_since(0, "members_tree")
+            .unwrap()
+            .map(|(seq, root_seq, leaf_index, old_value, new_value, root, _)| {
+                (seq, root_seq, leaf_index, old_value.0, new_value.0, root.0)
+            })
+            .collect();
+        // Note: leaf 0 is not changed by the 2nd modification
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].1, changes[0].2, changes[0].3, changes[0].4), (2, 0, Fr::from(0), Fr::from(2)));
+        assert_eq!((changes[1].1, changes[1].2, changes[1].3, changes[1].4), (3, 7, Fr::from(0), Fr::from(42)));
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        assert_eq!(
+            changes[1].5,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+
+        // Incremental sync (the cursor is the root sequence)
+        let last_root_seq = changes[0].1;
+        assert_eq!(pgfr_mtree_changes_since(last_root_seq, "members_tree").unwrap().count(), 1);
+        assert_eq!(pgfr_mtree_changes_since(0, "other_tree").unwrap().count(), 1);
+
+        // The changes of a dropped tree are removed
+        Spi::run("SELECT pgfr_mtree_drop('members_tree');").unwrap();
+        assert_eq!(Spi::get_one::<i64>("SELECT count(*) FROM pgfr_mtree_changes;").unwrap(), Some(1));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "merkle tree pgfr_mtree is not registered, its changes are not logged")]
+    fn test_mtree_changes_since_unregistered() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            "
+        ).unwrap();
+        pgfr_mtree_changes_since(0, "pgfr_mtree").unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+// std
+use std::collections::{BTreeMap, HashMap};
+// third-party
+use ark_bn254::Fr;
+use merkle_core::{build::build_levels, default_hashes, poseidon::poseidon_hash_};
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::registry::{mtree_check_depth, mtree_lock, mtree_table, mtree_touch};
+
+/// A node of the merkle tree table that is not consistent with the leaves
+struct MtreeIssue {
+    index_in_mtree: i64,
+    // missing | null | mismatch | out_of_range
+    issue: &'static str,
+    stored: Option<Fr>,
+    expected: Option<Fr>,
+}
+
+// Note: the nodes are checked by subtrees of this depth, so that pgfr_mtree_check does not load the whole table
+const CHECK_BLOCK_DEPTH: usize = 16;
+
+/// Stored values of the nodes start..end
+///
+/// Note: None if there is no row for the node, Some(None) if its value is NULL
+fn mtree_stored_nodes(table: &str, start: usize, end: usize) -> SpiResult<Vec<Option<Option<Fr>>>> {
+    let query = format!("SELECT index_in_mtree, value FROM {table} WHERE index_in_mtree >= $1 AND index_in_mtree < $2");
+    let mut stored = vec![None; end - start];
+    Spi::connect(|client| {
+        for row in client.select(&query, None, &[(start as i64).into(), (end as i64).into()])? {
+            let index_in_mtree = row.get::<i64>(1)?.expect("index_in_mtree is not null");
+            stored[index_in_mtree as usize - start] = Some(row.get::<PgFr>(2)?.map(|value| value.0));
+        }
+        Ok::<_, pgrx::spi::Error>(())
+    })?;
+    Ok(stored)
+}
+
+/// Compare the stored values of the nodes start..end with the expected values
+fn mtree_compare_nodes(
+    issues: &mut Vec<MtreeIssue>,
+    start: usize,
+    stored: Vec<Option<Option<Fr>>>,
+    expected: impl Fn(usize) -> Fr,
+) {
+    for (offset, value) in stored.into_iter().enumerate() {
+        let index = start + offset;
+        let expected = expected(index);
+        let issue = match value {
+            None => "missing",
+            Some(None) => "null",
+            Some(Some(value)) if value != expected => "mismatch",
+            Some(Some(_)) => continue,
+        };
+        issues.push(MtreeIssue { index_in_mtree: index as i64, issue, stored: value.flatten(), expected: Some(expected) });
+    }
+}
+
+/// Recompute all the internal nodes from the stored leaves (a missing or NULL leaf is an empty leaf)
+/// and compare them with the stored nodes
+///
+/// The bottom levels are checked one subtree (of depth CHECK_BLOCK_DEPTH) at a time, then the levels above
+/// the subtree roots are computed from these roots
+fn mtree_check(tree: &str, depth: i16) -> SpiResult<Vec<MtreeIssue>> {
+    mtree_check_depth(depth.into());
+    let table = mtree_table(tree, Some(depth))?;
+
+    let depth_ = depth as usize;
+    let node_count = (1usize << (depth_ + 1)) - 1;
+    let block_depth = depth_.min(CHECK_BLOCK_DEPTH);
+    let top_depth = depth_ - block_depth;
+    let level_hashes = default_hashes(depth_);
+
+    let mut issues = Vec::new();
+    Spi::connect(|client| {
+        let query = format!("SELECT index_in_mtree, value FROM {table} WHERE index_in_mtree < 0 OR index_in_mtree >= $1");
+        for row in client.select(&query, None, &[(node_count as i64).into()])? {
+            let index_in_mtree = row.get::<i64>(1)?.expect("index_in_mtree is not null");
+            let value = row.get::<PgFr>(2)?.map(|value| value.0);
+            issues.push(MtreeIssue { index_in_mtree, issue: "out_of_range", stored: value, expected: None });
+        }
+        Ok::<_, pgrx::spi::Error>(())
+    })?;
+
+    // Levels top_depth..=depth, one subtree at a time (the subtree root is at level top_depth)
+    let mut block_roots = vec![level_hashes[block_depth]; 1 << top_depth];
+    for (block, block_root) in block_roots.iter_mut().enumerate() {
+        let first_leaf = (1usize << depth_) - 1 + (block << block_depth);
+        let stored_leaves = mtree_stored_nodes(&table, first_leaf, first_leaf + (1 << block_depth))?;
+        let leaves: BTreeMap<usize, Fr> = stored_leaves
+            .iter()
+            .enumerate()
+            .filter_map(|(leaf_index, value)| value.flatten().map(|value| (leaf_index, value)))
+            .collect();
+        // Note: only the nodes that are not empty are computed, indexed in the subtree
+        let computed: HashMap<usize, Fr> = build_levels(block_depth, &leaves)
+            .into_iter()
+            .flatten()
+            .collect();
+        if let Some(root) = computed.get(&0) {
+            *block_root = *root;
+        }
+
+        let expected = |sub_level: usize, start: usize| {
+            let level = top_depth + sub_level;
+            let (computed, level_hashes) = (&computed, &level_hashes);
+            move |index: usize| computed
+                .get(&((1 << sub_level) - 1 + index - start))
+                .copied()
+                .unwrap_or(level_hashes[depth_ - level])
+        };
+        for sub_level in 0..block_depth {
+            let start = (1usize << (top_depth + sub_level)) - 1 + (block << sub_level);
+            let stored = mtree_stored_nodes(&table, start, start + (1 << sub_level))?;
+            mtree_compare_nodes(&mut issues, start, stored, expected(sub_level, start));
+        }
+        mtree_compare_nodes(&mut issues, first_leaf, stored_leaves, expected(block_depth, first_leaf));
+    }
+
+    // Levels 0..top_depth, computed from the subtree roots
+    let mut expected = vec![Fr::default(); (1 << top_depth) - 1];
+    let mut level_values = block_roots;
+    for level in (0..top_depth).rev() {
+        let height = depth_ - level;
+        level_values = level_values
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] if *left == level_hashes[height - 1] && *right == level_hashes[height - 1] => level_hashes[height],
+                [left, right] => poseidon_hash_(&[*left, *right]),
+                _ => unreachable!("a level has an even number of nodes"),
+            })
+            .collect();
+        expected[(1 << level) - 1..(1 << (level + 1)) - 1].copy_from_slice(&level_values);
+    }
+    let stored = mtree_stored_nodes(&table, 0, expected.len())?;
+    mtree_compare_nodes(&mut issues, 0, stored, |index| expected[index]);
+
+    issues.sort_by_key(|issue| issue.index_in_mtree);
+    Ok(issues)
+}
+
+/// Check that every node of the merkle tree is consistent with the leaves
+///
+/// Returns the nodes that are missing, whose value is NULL, that do not match the value computed from the leaves
+/// or whose index is out of range for the given depth
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_check(depth: i16, tree: default!(&str, "'pgfr_mtree'")) -> Result<
+    TableIterator<'static, (
+        name!(index_in_mtree, i64),
+        name!(issue, String),
+        name!(stored_value, Option<PgFr>),
+        name!(expected_value, Option<PgFr>),
+    )>,
+    pgrx::spi::Error
+> {
+    let issues = mtree_check(tree, depth)?;
+    Ok(TableIterator::new(
+        issues
+            .into_iter()
+            .map(|issue| (
+                issue.index_in_mtree,
+                issue.issue.to_string(),
+                issue.stored.map(PgFr),
+                issue.expected.map(PgFr),
+            ))
+    ))
+}
+
+/// Rewrite the internal nodes (and insert the missing rows) from the leaves. Returns the number of rows written
+///
+/// A NULL value is replaced (by an empty leaf for a leaf)
+///
+/// Note: the leaves are the source of truth (e.g. a leaf modified with a raw UPDATE is kept),
+///       rows with an out of range index are left untouched
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_mtree_repair(depth: i16, tree: default!(&str, "'pgfr_mtree'")) -> Result<i64, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    let mut missing = (Vec::new(), Vec::new());
+    let mut to_update = (Vec::new(), Vec::new());
+    let table = mtree_table(tree, None)?;
+    mtree_lock(tree)?;
+    for issue in mtree_check(tree, depth)? {
+        let rows = match issue.issue {
+            "missing" => &mut missing,
+            "null" | "mismatch" => &mut to_update,
+            _ => continue,
+        };
+        rows.0.push(issue.index_in_mtree);
+        rows.1.push(PgFr(issue.expected.expect("expected value for an in range node")));
+    }
+    let count = (missing.0.len() + to_update.0.len()) as i64;
+
+    Spi::run_with_args(
+        &format!(r#"
+        INSERT INTO {table} (index_in_mtree, value)
+        SELECT * FROM UNNEST($1::bigint[], $2::pgfr[])
+        "#),
+        &[missing.0.into(), missing.1.into()]
+    )?;
+    Spi::run_with_args(
+        &format!(r#"
+        UPDATE {table} AS m
+        SET value = data.new_value
+        FROM UNNEST($1::bigint[], $2::pgfr[]) AS data(i_index, new_value)
+        WHERE m.index_in_mtree = data.i_index
+        "#),
+        &[to_update.0.into(), to_update.1.into()]
+    )?;
+
+    if count > 0 {
+        // Note: the leaves are not changed (a NULL leaf was already an empty leaf) so there is no leaf change to log
+        mtree_touch(tree, Vec::new())?;
+    }
+    Ok(count)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+    use crate::registry::DEFAULT_TREE;
+
+    #[pg_test]
+    fn test_mtree_check_repair() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2');
+            "
+        ).unwrap();
+        assert_eq!(pgfr_mtree_check(3, DEFAULT_TREE).unwrap().count(), 0);
+
+        // Raw update of leaf 7 (node 14): its 3 ancestors (6, 2, 0) are wrong
+        // + a missing internal node (node 4), a NULL leaf (leaf 1, node 8) and a row out of range
+        Spi::run("
+            UPDATE pgfr_mtree SET value = '42' WHERE index_in_mtree = 14;
+            DELETE FROM pgfr_mtree WHERE index_in_mtree = 4;
+            UPDATE pgfr_mtree SET value = NULL WHERE index_in_mtree = 8;
+            INSERT INTO pgfr_mtree VALUES (15, '1');
+            "
+        ).unwrap();
+        let issues: Vec<(i64, String)> = pgfr_mtree_check(3, DEFAULT_TREE)
+            .unwrap()
+            .map(|(index, issue, _, _)| (index, issue))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (0, "mismatch".to_string()),
+                (2, "mismatch".to_string()),
+                (4, "missing".to_string()),
+                (6, "mismatch".to_string()),
+                (8, "null".to_string()),
+                (15, "out_of_range".to_string()),
+            ]
+        );
+
+        assert_eq!(pgfr_mtree_repair(3, DEFAULT_TREE).unwrap(), 5);
+        let issues: Vec<_> = pgfr_mtree_check(3, DEFAULT_TREE).unwrap().map(|(_, issue, _, _)| issue).collect();
+        assert_eq!(issues, vec!["out_of_range".to_string()]);
+
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        let root = Spi::get_one::<PgFr>("SELECT value FROM pgfr_mtree WHERE index_in_mtree = 0;").unwrap().unwrap();
+        assert_eq!(
+            root.0.to_string(),
+            "9164054056146260648413073295070635933539618302378139976693739565479035405901"
+        );
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "merkle tree depth must be between 1 and 32, received 0")]
+    fn test_mtree_check_depth_out_of_range() {
+        let _res = Spi::run("CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);").unwrap();
+        let _ = pgfr_mtree_check(0, DEFAULT_TREE).unwrap().count();
+    }
+}
@@ -0,0 +1,253 @@
+// std
+use std::collections::BTreeMap;
+// third-party
+use ark_bn254::Fr;
+use merkle_core::{build::build_proof, frontier::Frontier, proof::MerkleProof};
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+
+const FTREE_MAX_DEPTH: i16 = 32;
+
+// Append only merkle trees: only the right frontier (depth + 1 nodes) and the leaves are stored
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_ftree (
+    name text PRIMARY KEY,
+    depth smallint NOT NULL,
+    next_index bigint NOT NULL DEFAULT 0,
+    frontier pgfr[] NOT NULL,
+    root pgfr NOT NULL
+);
+CREATE TABLE pgfr_ftree_leaves (
+    tree text NOT NULL REFERENCES pgfr_ftree (name) ON DELETE CASCADE,
+    leaf_index bigint NOT NULL,
+    value pgfr NOT NULL,
+    PRIMARY KEY (tree, leaf_index)
+);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_ftree', '');
+SELECT pg_catalog.pg_extension_config_dump('pgfr_ftree_leaves', '');
+"#,
+    name = "create_ftree_tables",
+    requires = ["create_pgfr_type"]
+);
+
+/// Load (and lock until the end of the transaction) the frontier of a tree
+fn ftree_load(tree: &str) -> SpiResult<Frontier> {
+
+    let query = r#"
+        SELECT depth, next_index, frontier
+        FROM pgfr_ftree
+        WHERE name = $1
+        FOR UPDATE
+    "#;
+
+    let frontier = Spi::connect_mut(|client| {
+        let mut rows = client.update(query, None, &[tree.into()])?;
+        let Some(row) = rows.next() else {
+            return Ok::<_, pgrx::spi::Error>(None);
+        };
+        Ok(Some(Frontier {
+            depth: row.get::<i16>(1)?.expect("depth is not null") as usize,
+            next_index: row.get::<i64>(2)?.expect("next_index is not null") as u64,
+            branch: row
+                .get::<Vec<PgFr>>(3)?
+                .expect("frontier is not null")
+                .into_iter()
+                .map(|node| node.0)
+                .collect(),
+        }))
+    })?;
+
+    let Some(frontier) = frontier else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("append only merkle tree {tree} does not exist (cf. pgfr_ftree_create)")
+        );
+    };
+    Ok(frontier)
+}
+
+/// Create an append only merkle tree (only the frontier and the leaves are stored). Returns the root
+///
+/// Note: an append is O(depth) hashes and 2 writes, a proof is computed from all the leaves of the tree
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_ftree_create(name: &str, depth: i16) -> Result<PgFr, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    if !(1..=FTREE_MAX_DEPTH).contains(&depth) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("merkle tree depth must be between 1 and {FTREE_MAX_DEPTH}, received {depth}")
+        );
+    }
+
+    let frontier = Frontier::new(depth as usize);
+    let root = PgFr(frontier.root());
+    let branch: Vec<PgFr> = frontier.branch.into_iter().map(PgFr).collect();
+    Spi::run_with_args(
+        "INSERT INTO pgfr_ftree (name, depth, frontier, root) VALUES ($1, $2, $3, $4)",
+        &[name.into(), depth.into(), branch.into(), root.into()]
+    )?;
+
+    Ok(root)
+}
+
+/// Drop an append only merkle tree. Returns false if the tree does not exist
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_ftree_drop(name: &str) -> Result<bool, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    Ok(Spi::get_one_with_args::<bool>(
+        r#"
+        WITH deleted AS (DELETE FROM pgfr_ftree WHERE name = $1 RETURNING 1)
+        SELECT EXISTS (SELECT 1 FROM deleted)
+        "#,
+        &[name.into()]
+    )?.unwrap_or(false))
+}
+
+/// Append leaves to an append only merkle tree. Returns the leaf index of the first leaf
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_ftree_append_leaves(tree: &str, leaf_values: Vec<PgFr>) -> Result<i64, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    let mut frontier = ftree_load(tree)?;
+    let first_index = frontier.next_index as i64;
+
+    for leaf in leaf_values.iter() {
+        if let Err(e) = frontier.append(leaf.0) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+                e
+            );
+        }
+    }
+
+    let root = PgFr(frontier.root());
+    let branch: Vec<PgFr> = frontier.branch.into_iter().map(PgFr).collect();
+    Spi::run_with_args(
+        "UPDATE pgfr_ftree SET next_index = $2, frontier = $3, root = $4 WHERE name = $1",
+        &[tree.into(), (frontier.next_index as i64).into(), branch.into(), root.into()]
+    )?;
+    Spi::run_with_args(
+        r#"
+        INSERT INTO pgfr_ftree_leaves (tree, leaf_index, value)
+        SELECT $1, $2 + t.ord - 1, t.value
+        FROM UNNEST($3::pgfr[]) WITH ORDINALITY AS t(value, ord)
+        "#,
+        &[tree.into(), first_index.into(), leaf_values.into()]
+    )?;
+
+    Ok(first_index)
+}
+
+/// Append a leaf to an append only merkle tree. Returns its leaf index
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_ftree_append(tree: &str, leaf_value: PgFr) -> Result<i64, pgrx::spi::Error> {
+    pgfr_ftree_append_leaves(tree, vec![leaf_value])
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_ftree_get_root(tree: &str) -> Result<Option<PgFr>, pgrx::spi::Error> {
+    Spi::get_one_with_args(
+        "SELECT (SELECT root FROM pgfr_ftree WHERE name = $1)",
+        &[tree.into()]
+    )
+}
+
+/// Merkle proof of a leaf (same format as pgfr_mtree_get_proof), computed from all the leaves of the tree
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_ftree_get_proof(tree: &str, leaf_index: i64) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let (depth, next_index) = Spi::get_two_with_args::<i16, i64>(
+        "SELECT r.depth, r.next_index FROM (SELECT 1) AS one LEFT JOIN pgfr_ftree r ON r.name = $1",
+        &[tree.into()]
+    )?;
+    let (Some(depth), Some(next_index)) = (depth, next_index) else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("append only merkle tree {tree} does not exist (cf. pgfr_ftree_create)")
+        );
+    };
+    if leaf_index < 0 || leaf_index >= next_index {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("leaf index {leaf_index} is out of range for merkle tree {tree} ({next_index} leaves)")
+        );
+    }
+
+    let leaves = Spi::connect(|client| {
+        let mut leaves = BTreeMap::new();
+        let query = "SELECT leaf_index, value FROM pgfr_ftree_leaves WHERE tree = $1";
+        for row in client.select(query, None, &[tree.into()])? {
+            let index = row.get::<i64>(1)?.expect("leaf_index is not null");
+            let value = row.get::<PgFr>(2)?.expect("value is not null");
+            leaves.insert(index as usize, value.0);
+        }
+        Ok::<_, pgrx::spi::Error>(leaves)
+    })?;
+
+    let path: Vec<(i64, Fr)> = build_proof(depth as usize, &leaves, leaf_index as usize);
+    Ok(MerkleProof { path }.to_bytes())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use super::*;
+
+    #[pg_test]
+    fn test_ftree_append() {
+        let root = pgfr_ftree_create("deposits", 3).unwrap();
+        // Same root as an empty pgfr_mtree of depth 3
+        assert_eq!(
+            root.0,
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+
+        assert_eq!(pgfr_ftree_append("deposits", PgFr(Fr::from(2))).unwrap(), 0);
+        let values = ["0", "0", "0", "0", "0", "0", "42"].map(|v| PgFr(Fr::from_str(v).unwrap()));
+        assert_eq!(pgfr_ftree_append_leaves("deposits", values.to_vec()).unwrap(), 1);
+
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        let root = pgfr_ftree_get_root("deposits").unwrap().unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        for (leaf_index, leaf) in [(0, Fr::from(2)), (3, Fr::from(0)), (7, Fr::from(42))] {
+            let proof = MerkleProof::from_bytes(&pgfr_ftree_get_proof("deposits", leaf_index).unwrap()).unwrap();
+            assert!(proof.verify(leaf, root.0));
+        }
+
+        assert!(pgfr_ftree_drop("deposits").unwrap());
+        assert!(pgfr_ftree_get_root("deposits").unwrap().is_none());
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Merkle tree of depth 3 is full")]
+    fn test_ftree_full() {
+        pgfr_ftree_create("deposits", 3).unwrap();
+        let values = vec![PgFr(Fr::from(1)); 9];
+        pgfr_ftree_append_leaves("deposits", values).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "table pgfr_ftree_leaves can only be modified by the pg_merkle_tree functions")]
+    fn test_ftree_protected() {
+        pgfr_ftree_create("deposits", 3).unwrap();
+        Spi::run("DELETE FROM pgfr_ftree_leaves;").unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+// std
+use std::cell::Cell;
+// pgrx
+use pgrx::{
+    guc::{GucContext, GucFlags, GucRegistry, GucSetting},
+    prelude::*,
+};
+
+thread_local! {
+    // Note: > 0 while a function of the extension modifies a merkle tree table (functions can be nested)
+    static WRITE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+static ALLOW_DIRECT_WRITE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// Register pg_merkle_tree.allow_direct_write (cf. _PG_init)
+///
+/// Note: only a superuser can set it (an unregistered placeholder could be set by any role)
+pub(crate) fn mtree_guard_init() {
+    GucRegistry::define_bool_guc(
+        c"pg_merkle_tree.allow_direct_write",
+        c"Allow the direct modification of the merkle tree tables",
+        c"Disables the check of pgfr_mtree_guard_trigger (e.g. to restore a dump)",
+        &ALLOW_DIRECT_WRITE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+}
+
+/// Allow the modification of the protected tables while alive (cf. pgfr_mtree_guard_trigger)
+///
+/// Note: the guard is dropped on error too (pgrx unwinds the Rust stack)
+pub(crate) struct MtreeWriteGuard;
+
+impl MtreeWriteGuard {
+    pub(crate) fn new() -> Self {
+        WRITE_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        MtreeWriteGuard
+    }
+}
+
+impl Drop for MtreeWriteGuard {
+    fn drop(&mut self) {
+        WRITE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// true if called (directly or not) from a function of the extension that modifies a merkle tree
+/// or if pg_merkle_tree.allow_direct_write is on
+#[pg_extern(volatile, parallel_unsafe)]
+fn pgfr_mtree_write_allowed() -> bool {
+    WRITE_DEPTH.with(|depth| depth.get() > 0) || ALLOW_DIRECT_WRITE.get()
+}
+
+// Reject the modifications of a table that are not done by the extension functions
+// (e.g. UPDATE pgfr_mtree SET value = ... would break the merkle tree)
+// Note: pg_merkle_tree.allow_direct_write = on (superuser only) disables the check (e.g. to restore a dump)
+extension_sql!(
+    r#"
+CREATE FUNCTION pgfr_mtree_guard_trigger() RETURNS trigger
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF NOT pgfr_mtree_write_allowed() THEN
+        RAISE EXCEPTION 'table % can only be modified by the pg_merkle_tree functions', TG_TABLE_NAME
+            USING ERRCODE = 'insufficient_privilege',
+                  HINT = 'Use pgfr_mtree_set_leaf / pgfr_mtree_set_leaves (or pgfr_mtree_unprotect)';
+    END IF;
+    RETURN NULL;
+END;
+$$;
+
+CREATE FUNCTION pgfr_mtree_protect(target regclass) RETURNS void
+LANGUAGE plpgsql AS $$
+BEGIN
+    EXECUTE format(
+        'CREATE TRIGGER pgfr_mtree_guard BEFORE INSERT OR UPDATE OR DELETE OR TRUNCATE ON %s
+         FOR EACH STATEMENT EXECUTE FUNCTION pgfr_mtree_guard_trigger()',
+        target
+    );
+END;
+$$;
+
+CREATE FUNCTION pgfr_mtree_unprotect(target regclass) RETURNS void
+LANGUAGE plpgsql AS $$
+BEGIN
+    EXECUTE format('DROP TRIGGER IF EXISTS pgfr_mtree_guard ON %s', target);
+END;
+$$;
+
+-- Note: the functions of the extension run with the privileges of the caller, so every role can modify the tables
+--       and the guard trigger restricts the modifications to the extension functions (no TRUNCATE)
+GRANT SELECT, INSERT, UPDATE, DELETE ON pgfr_mtree_preimage, pgfr_smt, pgfr_smt_leaves, pgfr_imt, pgfr_imt_leaves, pgfr_mtree_registry, pgfr_mtree_changes, pgfr_ftree, pgfr_ftree_leaves TO PUBLIC;
+GRANT USAGE ON SEQUENCE pgfr_mtree_registry_tree_id_seq, pgfr_mtree_changes_seq_seq TO PUBLIC;
+SELECT pgfr_mtree_protect(t)
+FROM unnest(ARRAY['pgfr_mtree_preimage', 'pgfr_smt', 'pgfr_smt_leaves', 'pgfr_imt', 'pgfr_imt_leaves', 'pgfr_mtree_registry', 'pgfr_mtree_changes', 'pgfr_ftree', 'pgfr_ftree_leaves']::regclass[]) AS t;
+"#,
+    name = "pgfr_mtree_guard",
+    requires = [
+        pgfr_mtree_write_allowed,
+        "create_preimage_table",
+        "create_smt_tables",
+        "create_imt_tables",
+        "create_mtree_registry",
+        "create_mtree_changes",
+        "create_ftree_tables"
+    ]
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+
+    fn create_protected_mtree() {
+        Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_protect('pgfr_mtree');
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2');
+            "
+        ).unwrap();
+    }
+
+    #[pg_test]
+    fn test_mtree_guard() {
+        create_protected_mtree();
+        assert!(!pgfr_mtree_write_allowed());
+        {
+            let _guard = MtreeWriteGuard::new();
+            let _guard_2 = MtreeWriteGuard::new();
+            assert!(pgfr_mtree_write_allowed());
+        }
+        assert!(!pgfr_mtree_write_allowed());
+
+        // Extension functions can still modify the tree
+        Spi::run("
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[7], ARRAY['42']::pgfr[]);
+            SELECT pgfr_mtree_build(3::smallint, ARRAY['2', '0', '0', '0', '0', '0', '0', '42']::pgfr[]);
+            SELECT pgfr_smt_insert('2', '42');
+            "
+        ).unwrap();
+
+        // Escape hatch (e.g. pg_restore, the tests run as superuser) then back to normal
+        Spi::run("
+            SET LOCAL pg_merkle_tree.allow_direct_write = on;
+            UPDATE pgfr_mtree SET value = '3' WHERE index_in_mtree = 7;
+            SET LOCAL pg_merkle_tree.allow_direct_write = off;
+            SELECT pgfr_mtree_unprotect('pgfr_mtree');
+            UPDATE pgfr_mtree SET value = '2' WHERE index_in_mtree = 7;
+            "
+        ).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "table pgfr_mtree can only be modified by the pg_merkle_tree functions")]
+    fn test_mtree_guard_update() {
+        create_protected_mtree();
+        Spi::run("UPDATE pgfr_mtree SET value = '3' WHERE index_in_mtree = 7;").unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "permission denied to set parameter")]
+    fn test_mtree_guard_allow_direct_write_superuser_only() {
+        Spi::run("
+            CREATE ROLE pgfr_mtree_app;
+            SET LOCAL ROLE pgfr_mtree_app;
+            SET LOCAL pg_merkle_tree.allow_direct_write = on;
+            "
+        ).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "table pgfr_smt_leaves can only be modified by the pg_merkle_tree functions")]
+    fn test_smt_guard_delete() {
+        Spi::run("
+            SELECT pgfr_smt_insert('2', '42');
+            DELETE FROM pgfr_smt_leaves;
+            "
+        ).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+// std
+use std::collections::BTreeMap;
+// third-party
+use ark_bn254::Fr;
+use merkle_core::{
+    default_hashes,
+    merkle_tree_utils::{node_parent, node_sibling},
+    proof::MerkleProof,
+};
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::merkle_tree::{mtree_get_hashes, mtree_get_nodes, mtree_leaf_node, pgfr_mtree_get_root, pgfr_mtree_set_leaves};
+use crate::registry::{mtree_lock, mtree_table};
+
+/// A registered tree as of a previous root sequence
+pub(crate) struct MtreeState {
+    pub(crate) table: String,
+    pub(crate) depth: i16,
+    /// Nodes whose value is not the stored value (the leaves modified after root_seq and their ancestors)
+    pub(crate) nodes: BTreeMap<i64, PgFr>,
+    /// Values as of root_seq of the leaves modified after root_seq
+    pub(crate) leaves: BTreeMap<i64, Fr>,
+    pub(crate) root: Fr,
+}
+
+/// Reconstruct the state of a registered tree as of the given root sequence by reverting
+/// (in memory) the leaf changes logged after it (cf. pgfr_mtree_changes)
+///
+/// The resulting root is checked against the root recorded in the change log
+pub(crate) fn mtree_state_at(tree: &str, root_seq: i64) -> SpiResult<MtreeState> {
+
+    // Note: the LEFT JOIN always returns 1 row (NULLs if the tree is not registered)
+    let registered = Spi::get_three_with_args::<i32, i16, i64>(
+        r#"
+        SELECT r.tree_id, r.depth, r.root_seq
+        FROM (SELECT 1) AS one
+        LEFT JOIN pgfr_mtree_registry r ON r.name = $1
+        "#,
+        &[tree.into()]
+    )?;
+    let (Some(tree_id), Some(depth), Some(current_seq)) = registered else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("merkle tree {tree} is not registered (cf. pgfr_mtree_create)")
+        );
+    };
+    // Note: root sequence 1 is the empty tree (cf. pgfr_mtree_create)
+    if root_seq < 1 || root_seq > current_seq {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("root sequence {root_seq} is out of range for merkle tree {tree} (1..={current_seq})")
+        );
+    }
+    let table = mtree_table(tree, None)?;
+
+    // The value of a leaf as of root_seq is the old value of its first change after root_seq
+    let query = r#"
+        SELECT DISTINCT ON (leaf_index) leaf_index, old_value
+        FROM pgfr_mtree_changes
+        WHERE tree_id = $1 AND root_seq > $2
+        ORDER BY leaf_index, seq
+    "#;
+    let leaves = Spi::connect(|client| {
+        let mut leaves = BTreeMap::new();
+        for row in client.select(query, None, &[tree_id.into(), root_seq.into()])? {
+            let leaf_index = row.get::<i64>(1)?.expect("leaf_index is not null");
+            let old_value = row.get::<PgFr>(2)?.expect("old_value is not null");
+            leaves.insert(leaf_index, old_value.0);
+        }
+        Ok::<_, pgrx::spi::Error>(leaves)
+    })?;
+
+    let mut nodes: BTreeMap<i64, PgFr> = leaves
+        .iter()
+        .map(|(leaf_index, value)| (mtree_leaf_node(depth, *leaf_index) as i64, PgFr(*value)))
+        .collect();
+    let leaf_nodes = nodes.keys().map(|node| *node as usize).collect();
+    mtree_get_hashes(&table, leaf_nodes, &mut nodes);
+
+    let root = match nodes.get(&0) {
+        Some(root) => root.0,
+        None => pgfr_mtree_get_root(tree)?.expect("merkle tree is initialized").0,
+    };
+
+    // Note: a root sequence without leaf change (e.g. pgfr_mtree_repair) has the root of the previous change
+    let recorded_root = Spi::get_one_with_args::<PgFr>(
+        r#"
+        SELECT (
+            SELECT root FROM pgfr_mtree_changes
+            WHERE tree_id = $1 AND root_seq <= $2
+            ORDER BY seq DESC
+            LIMIT 1
+        )
+        "#,
+        &[tree_id.into(), root_seq.into()]
+    )?
+        .map(|root| root.0)
+        .unwrap_or_else(|| default_hashes(depth as usize)[depth as usize]);
+
+    if root != recorded_root {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+            format!(
+                "merkle tree {tree} history is inconsistent: root at sequence {root_seq} is {root} but {recorded_root} was recorded"
+            )
+        );
+    }
+
+    Ok(MtreeState { table, depth, nodes, leaves, root })
+}
+
+/// Root of a registered tree as of the given root sequence
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_get_root_at(root_seq: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<PgFr, pgrx::spi::Error> {
+    Ok(PgFr(mtree_state_at(tree, root_seq)?.root))
+}
+
+/// Merkle proof of a leaf as of the given root sequence (i.e. against the root returned by pgfr_mtree_get_root_at)
+///
+/// Note: only the trees created with pgfr_mtree_create keep a history (cf. pgfr_mtree_changes_since)
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_get_proof_at(leaf_index: i64, root_seq: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let state = mtree_state_at(tree, root_seq)?;
+
+    let mut left_or_right = Vec::new();
+    let mut siblings = Vec::new();
+    let mut index = mtree_leaf_node(state.depth, leaf_index);
+    while let Some(parent) = node_parent(index) {
+        // Note: left children have an odd index
+        left_or_right.push(if index & 1 == 1 { 0 } else { 1 });
+        siblings.push(node_sibling(index) as i64);
+        index = parent;
+    }
+
+    // Siblings that are not modified after root_seq are read from the db
+    let to_read: Vec<i64> = siblings
+        .iter()
+        .filter(|sibling| !state.nodes.contains_key(sibling))
+        .copied()
+        .collect();
+    let read_values: BTreeMap<i64, Fr> = to_read
+        .iter()
+        .copied()
+        .zip(mtree_get_nodes(&state.table, to_read.clone()))
+        .collect();
+
+    let path = left_or_right
+        .into_iter()
+        .zip(siblings)
+        .map(|(flag, sibling)| {
+            let value = state.nodes
+                .get(&sibling)
+                .map(|value| value.0)
+                .unwrap_or_else(|| read_values[&sibling]);
+            (flag, value)
+        })
+        .collect();
+
+    Ok(MerkleProof { path }.to_bytes())
+}
+
+/// Restore a registered tree to its state as of the given root sequence. Returns the restored root
+///
+/// The rollback is a new modification of the tree (logged in pgfr_mtree_changes, with a new root sequence)
+/// so it can be rolled back too.
+/// Note: the preimages of the restored leaves are not restored (cf. pgfr_mtree_set_leaf_preimage)
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_mtree_rollback_to(root_seq: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<PgFr, pgrx::spi::Error> {
+
+    // Note: the state is computed from the current nodes, which must not change until the leaves are restored
+    mtree_lock(tree)?;
+    let state = mtree_state_at(tree, root_seq)?;
+    if state.leaves.is_empty() {
+        return Ok(PgFr(state.root));
+    }
+
+    let (indices, leaf_values): (Vec<i64>, Vec<PgFr>) = state.leaves
+        .into_iter()
+        .map(|(leaf_index, value)| (leaf_index, PgFr(value)))
+        .unzip();
+    pgfr_mtree_set_leaves(state.depth, indices, leaf_values, tree)?;
+
+    let root = pgfr_mtree_get_root(tree)?.expect("merkle tree is initialized");
+    if root.0 != state.root {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+            format!("Rollback of merkle tree {tree} to sequence {root_seq}: expected root {} but got {}", state.root, root.0)
+        );
+    }
+
+    Ok(root)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use super::*;
+
+    #[pg_test]
+    fn test_mtree_get_proof_at() {
+        let _res = Spi::run("
+            SELECT pgfr_mtree_create('members_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[0, 7], ARRAY['2', '42']::pgfr[], 'members_tree');
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '5', 'members_tree');
+            SELECT pgfr_mtree_set_leaf(3::smallint, 3, '3', 'members_tree');
+            "
+        ).unwrap();
+
+        // Root sequence 2: leaf 0 = 2, leaf 7 = 42 (same root as zerokit_ref)
+        let root = pgfr_mtree_get_root_at(2, "members_tree").unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        for (leaf_index, leaf) in [(0, Fr::from(2)), (3, Fr::from(0)), (7, Fr::from(42))] {
+            let proof = pgfr_mtree_get_proof_at(leaf_index, 2, "members_tree").unwrap();
+            let proof = MerkleProof::from_bytes(&proof).unwrap();
+            assert!(proof.verify(leaf, root.0));
+        }
+
+        // Root sequence 1: empty tree
+        let empty_root = pgfr_mtree_get_root_at(1, "members_tree").unwrap();
+        assert_eq!(empty_root.0, default_hashes(3)[3]);
+
+        // Current root sequence: same proof as pgfr_mtree_get_proof
+        let proof_at = pgfr_mtree_get_proof_at(3, 4, "members_tree").unwrap();
+        let proof = Spi::get_one::<Vec<u8>>("SELECT pgfr_mtree_get_proof(3::smallint, 3, 'members_tree');")
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof_at, proof);
+    }
+
+    #[pg_test]
+    fn test_mtree_rollback_to() {
+        let _res = Spi::run("
+            SELECT pgfr_mtree_create('members_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[0, 7], ARRAY['2', '42']::pgfr[], 'members_tree');
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '5', 'members_tree');
+            SELECT pgfr_mtree_set_leaf(3::smallint, 3, '3', 'members_tree');
+            "
+        ).unwrap();
+        let current_root = pgfr_mtree_get_root("members_tree").unwrap().unwrap();
+
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        let root = pgfr_mtree_rollback_to(2, "members_tree").unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        let leaves = Spi::get_two::<PgFr, PgFr>(
+            "SELECT (SELECT value FROM members_tree WHERE index_in_mtree = 7), (SELECT value FROM members_tree WHERE index_in_mtree = 10);"
+        ).unwrap();
+        assert_eq!((leaves.0.unwrap().0, leaves.1.unwrap().0), (Fr::from(2), Fr::from(0)));
+        assert_eq!(Spi::get_one::<i64>("SELECT count(*) FROM pgfr_mtree_check(3::smallint, 'members_tree');").unwrap(), Some(0));
+
+        // The rollback is logged (root sequence 5) and can be rolled back
+        let root = pgfr_mtree_rollback_to(4, "members_tree").unwrap();
+        assert_eq!(root.0, current_root.0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "root sequence 5 is out of range for merkle tree members_tree")]
+    fn test_mtree_get_proof_at_out_of_range() {
+        let _res = Spi::run("SELECT pgfr_mtree_create('members_tree', 3::smallint);").unwrap();
+        pgfr_mtree_get_proof_at(0, 5, "members_tree").unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+// third-party
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use merkle_core::{poseidon::poseidon_hash_, proof::proof_root};
+use num_bigint::BigUint;
+use once_cell::sync::Lazy;
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::sparse_storage::SparseStorage;
+
+const IMT_DEPTH: usize = 32;
+
+static IMT_STORAGE: Lazy<SparseStorage> = Lazy::new(|| SparseStorage::new("pgfr_imt", IMT_DEPTH));
+
+// Note: value_key is the value as 32 bytes big endian so that the bytea ordering is the Fr ordering
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_imt (
+    level smallint NOT NULL,
+    path bytea NOT NULL,
+    value pgfr NOT NULL,
+    PRIMARY KEY (level, path)
+);
+CREATE TABLE pgfr_imt_leaves (
+    leaf_index bigint PRIMARY KEY,
+    value pgfr NOT NULL,
+    value_key bytea NOT NULL UNIQUE,
+    next_index bigint NOT NULL,
+    next_value pgfr NOT NULL
+);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_imt', '');
+SELECT pg_catalog.pg_extension_config_dump('pgfr_imt_leaves', '');
+"#,
+    name = "create_imt_tables",
+    requires = ["create_pgfr_type"]
+);
+
+/// A leaf of the indexed merkle tree: a node of a linked list sorted by value
+/// (next_index = 0 for the leaf with the highest value)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ImtLeaf {
+    index: i64,
+    value: Fr,
+    next_index: i64,
+    next_value: Fr,
+}
+
+impl ImtLeaf {
+    fn hash(&self) -> Fr {
+        poseidon_hash_(&[self.value, Fr::from(self.next_index), self.next_value])
+    }
+
+    /// true if value is strictly between this leaf value and the next leaf value
+    fn is_low_leaf_of(&self, value: &Fr) -> bool {
+        self.value < *value && (self.next_index == 0 || *value < self.next_value)
+    }
+
+    fn serialize_with_proof(&self, proof_data: &[(i64, Fr)]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.index.serialize_compressed(&mut buffer).expect("Serialization failed");
+        self.value.serialize_compressed(&mut buffer).expect("Serialization failed");
+        self.next_index.serialize_compressed(&mut buffer).expect("Serialization failed");
+        self.next_value.serialize_compressed(&mut buffer).expect("Serialization failed");
+        proof_data.serialize_compressed(&mut buffer).expect("Serialization failed");
+        buffer
+    }
+
+    fn deserialize_with_proof(mut bytes: &[u8]) -> Result<(Self, Vec<(i64, Fr)>), ark_serialize::SerializationError> {
+        let leaf = ImtLeaf {
+            index: i64::deserialize_compressed(&mut bytes)?,
+            value: Fr::deserialize_compressed(&mut bytes)?,
+            next_index: i64::deserialize_compressed(&mut bytes)?,
+            next_value: Fr::deserialize_compressed(&mut bytes)?,
+        };
+        let proof_data = Vec::<(i64, Fr)>::deserialize_compressed(&mut bytes)?;
+        Ok((leaf, proof_data))
+    }
+}
+
+fn imt_value_key(value: &Fr) -> Vec<u8> {
+    value.into_bigint().to_bytes_be()
+}
+
+fn imt_position(leaf_index: i64) -> BigUint {
+    BigUint::from(leaf_index as u64)
+}
+
+/// The leaf with the highest value strictly lower than value
+fn imt_get_low_leaf(value: &Fr) -> SpiResult<Option<ImtLeaf>> {
+
+    let query = r#"
+        SELECT leaf_index, value, next_index, next_value
+        FROM pgfr_imt_leaves
+        WHERE value_key < $1
+        ORDER BY value_key DESC
+        LIMIT 1
+    "#;
+
+    Spi::connect(|client| {
+        let leaf = client
+            .select(query, None, &[imt_value_key(value).into()])?
+            .into_iter()
+            .next()
+            .map(|row| {
+                Ok::<_, pgrx::spi::Error>(ImtLeaf {
+                    index: row.get::<i64>(1)?.expect("leaf_index is not null"),
+                    value: row.get::<PgFr>(2)?.expect("value is not null").0,
+                    next_index: row.get::<i64>(3)?.expect("next_index is not null"),
+                    next_value: row.get::<PgFr>(4)?.expect("next_value is not null").0,
+                })
+            })
+            .transpose()?;
+        Ok(leaf)
+    })
+}
+
+/// Lock the leaves until the end of the transaction: the linked list and the next leaf index
+/// must not change between the read of the low leaf and the insert
+fn imt_lock() -> SpiResult<()> {
+    Spi::run("LOCK TABLE pgfr_imt_leaves IN EXCLUSIVE MODE")
+}
+
+fn imt_set_leaf(leaf: &ImtLeaf) -> SpiResult<Fr> {
+    let _guard = MtreeWriteGuard::new();
+
+    let query = r#"
+        INSERT INTO pgfr_imt_leaves (leaf_index, value, value_key, next_index, next_value)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (leaf_index) DO UPDATE
+        SET next_index = EXCLUDED.next_index, next_value = EXCLUDED.next_value
+    "#;
+
+    Spi::run_with_args(
+        query,
+        &[
+            leaf.index.into(),
+            PgFr(leaf.value).into(),
+            imt_value_key(&leaf.value).into(),
+            leaf.next_index.into(),
+            PgFr(leaf.next_value).into(),
+        ]
+    )?;
+
+    IMT_STORAGE.set_leaf(&imt_position(leaf.index), leaf.hash())
+}
+
+/// Init the indexed merkle tree with its first leaf (0, 0, 0)
+///
+/// Fails if the tree is already initialized
+#[pg_extern(parallel_unsafe)]
+fn pgfr_imt_init() -> Result<PgFr, pgrx::spi::Error> {
+    imt_lock()?;
+
+    let is_initialized = Spi::get_one::<bool>("SELECT EXISTS (SELECT 1 FROM pgfr_imt_leaves WHERE leaf_index = 0)")?
+        .unwrap_or(false);
+    if is_initialized {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DUPLICATE_OBJECT,
+            "indexed merkle tree is already initialized"
+        );
+    }
+
+    let root = imt_set_leaf(&ImtLeaf {
+        index: 0,
+        value: Fr::default(),
+        next_index: 0,
+        next_value: Fr::default(),
+    })?;
+    Ok(PgFr(root))
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_imt_get_root() -> Result<PgFr, pgrx::spi::Error> {
+    Ok(PgFr(IMT_STORAGE.get_root()?))
+}
+
+/// Insert a value in the indexed merkle tree. Returns the new root
+#[pg_extern(parallel_unsafe)]
+fn pgfr_imt_insert(value: PgFr) -> Result<PgFr, pgrx::spi::Error> {
+    imt_lock()?;
+
+    let Some(low_leaf) = imt_get_low_leaf(&value.0)? else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!("Cannot insert {}: indexed merkle tree is not initialized (or value is 0)", value.0)
+        );
+    };
+
+    if !low_leaf.is_low_leaf_of(&value.0) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNIQUE_VIOLATION,
+            format!("value {} is already in the indexed merkle tree", value.0)
+        );
+    }
+
+    // Note: leaves are appended so the next leaf index is the number of leaves
+    let leaf_index = Spi::get_one::<i64>("SELECT count(*) FROM pgfr_imt_leaves")?
+        .expect("count is not null");
+    if leaf_index >= (1i64 << IMT_DEPTH) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+            "indexed merkle tree is full"
+        );
+    }
+
+    // The new leaf is inserted in the linked list right after the low leaf
+    let new_leaf = ImtLeaf {
+        index: leaf_index,
+        value: value.0,
+        next_index: low_leaf.next_index,
+        next_value: low_leaf.next_value,
+    };
+    let low_leaf = ImtLeaf {
+        next_index: leaf_index,
+        next_value: value.0,
+        ..low_leaf
+    };
+
+    imt_set_leaf(&low_leaf)?;
+    let root = imt_set_leaf(&new_leaf)?;
+    Ok(PgFr(root))
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_imt_low_leaf(value: PgFr) -> Result<
+    TableIterator<'static, (
+        name!(leaf_index, i64),
+        name!(value, PgFr),
+        name!(next_index, i64),
+        name!(next_value, PgFr),
+    )>,
+    pgrx::spi::Error
+> {
+    let low_leaf = imt_get_low_leaf(&value.0)?;
+    Ok(TableIterator::new(
+        low_leaf
+            .into_iter()
+            .map(|leaf| (leaf.index, PgFr(leaf.value), leaf.next_index, PgFr(leaf.next_value)))
+    ))
+}
+
+/// Low leaf of a value + its membership proof. This is a non-membership proof of the value
+///
+/// Encoding: leaf_index (i64), value (Fr), next_index (i64), next_value (Fr), proof (Vec<(i64, Fr)>)
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_imt_low_leaf_proof(value: PgFr) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let low_leaf = match imt_get_low_leaf(&value.0)? {
+        Some(low_leaf) if low_leaf.is_low_leaf_of(&value.0) => low_leaf,
+        _ => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_UNIQUE_VIOLATION,
+                format!("value {} is in the indexed merkle tree (or tree is not initialized)", value.0)
+            );
+        }
+    };
+
+    let proof_data = IMT_STORAGE.get_proof(&imt_position(low_leaf.index))?;
+    Ok(low_leaf.serialize_with_proof(&proof_data))
+}
+
+/// Verify a proof returned by pgfr_imt_low_leaf_proof: value is not in the tree with this root
+#[pg_extern(immutable, strict, parallel_safe)]
+fn pgfr_imt_verify_low_leaf_proof(value: PgFr, proof: &[u8], root: PgFr) -> bool {
+
+    let (low_leaf, proof_data) = match ImtLeaf::deserialize_with_proof(proof) {
+        Ok(res) => res,
+        Err(e) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+                format!("Invalid encoding for indexed merkle tree proof: {}", e)
+            );
+        }
+    };
+
+    // The proof path must be the one given by the low leaf index
+    let position = imt_position(low_leaf.index);
+    let valid_path = proof_data.len() == IMT_DEPTH
+        && proof_data
+            .iter()
+            .enumerate()
+            .all(|(bit, (left_or_right, _))| *left_or_right == i64::from(position.bit(bit as u64)));
+
+    valid_path
+        && low_leaf.is_low_leaf_of(&value.0)
+        && proof_root(low_leaf.hash(), &proof_data) == root.0
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+
+    #[pg_test]
+    fn test_imt_insert() {
+
+        pgfr_imt_init().unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(30))).unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(10))).unwrap();
+        let root = pgfr_imt_insert(PgFr(Fr::from(20))).unwrap();
+        assert_eq!(pgfr_imt_get_root().unwrap().0, root.0);
+
+        // Linked list: 0 -> 10 -> 20 -> 30 -> end
+        let low_leaf = imt_get_low_leaf(&Fr::from(25)).unwrap().unwrap();
+        assert_eq!(
+            low_leaf,
+            ImtLeaf { index: 3, value: Fr::from(20), next_index: 1, next_value: Fr::from(30) }
+        );
+        let low_leaf = imt_get_low_leaf(&Fr::from(31)).unwrap().unwrap();
+        assert_eq!(
+            low_leaf,
+            ImtLeaf { index: 1, value: Fr::from(30), next_index: 0, next_value: Fr::from(0) }
+        );
+        let low_leaf = imt_get_low_leaf(&Fr::from(5)).unwrap().unwrap();
+        assert_eq!(
+            low_leaf,
+            ImtLeaf { index: 0, value: Fr::from(0), next_index: 2, next_value: Fr::from(10) }
+        );
+
+        // Root computed from the leaves (the 4 leaves are in the first subtree of 4 leaves)
+        let leaves = [
+            ImtLeaf { index: 0, value: Fr::from(0), next_index: 2, next_value: Fr::from(10) },
+            ImtLeaf { index: 1, value: Fr::from(30), next_index: 0, next_value: Fr::from(0) },
+            ImtLeaf { index: 2, value: Fr::from(10), next_index: 3, next_value: Fr::from(20) },
+            ImtLeaf { index: 3, value: Fr::from(20), next_index: 1, next_value: Fr::from(30) },
+        ];
+        let hashes = merkle_core::default_hashes(IMT_DEPTH);
+        let mut node = poseidon_hash_(&[
+            poseidon_hash_(&[leaves[0].hash(), leaves[1].hash()]),
+            poseidon_hash_(&[leaves[2].hash(), leaves[3].hash()]),
+        ]);
+        for default_hash in hashes.iter().take(IMT_DEPTH).skip(2) {
+            node = poseidon_hash_(&[node, *default_hash]);
+        }
+        assert_eq!(root.0, node);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "is already in the indexed merkle tree")]
+    fn test_imt_insert_twice() {
+        pgfr_imt_init().unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(30))).unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(30))).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "indexed merkle tree is already initialized")]
+    fn test_imt_init_twice() {
+        pgfr_imt_init().unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(30))).unwrap();
+        pgfr_imt_init().unwrap();
+    }
+
+    #[pg_test]
+    fn test_imt_low_leaf_proof() {
+
+        pgfr_imt_init().unwrap();
+        pgfr_imt_insert(PgFr(Fr::from(10))).unwrap();
+        let root = pgfr_imt_insert(PgFr(Fr::from(30))).unwrap();
+
+        let proof = pgfr_imt_low_leaf_proof(PgFr(Fr::from(20))).unwrap();
+        assert!(pgfr_imt_verify_low_leaf_proof(PgFr(Fr::from(20)), &proof, root));
+        assert!(pgfr_imt_verify_low_leaf_proof(PgFr(Fr::from(29)), &proof, root));
+        // 30 is in the tree (and 31 is not covered by this low leaf)
+        assert!(!pgfr_imt_verify_low_leaf_proof(PgFr(Fr::from(30)), &proof, root));
+        assert!(!pgfr_imt_verify_low_leaf_proof(PgFr(Fr::from(31)), &proof, root));
+        // Wrong root
+        assert!(!pgfr_imt_verify_low_leaf_proof(PgFr(Fr::from(20)), &proof, PgFr(Fr::from(1))));
+    }
+}
@@ -0,0 +1,115 @@
+// third-party
+use serde_json::{json, Value};
+// pgrx
+use pgrx::{
+    prelude::*,
+    Json, JsonB,
+};
+use crate::PgFr;
+use crate::merkle_tree::{mtree_get_nodes, mtree_get_proof, mtree_leaf_node, pgfr_mtree_get_root};
+use crate::registry::mtree_table;
+
+/// Merkle proof of a leaf as json, in the snarkjs / circom input convention (values as decimal strings):
+/// {"root": "...", "leaf": "...", "path_elements": [...], "path_indices": [...]}
+///
+/// Note: path_indices[i] is 0 if the node at level i is a left child (as in pgfr_mtree_get_proof)
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_get_proof_json(depth: i16, leaf_index: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<JsonB, pgrx::spi::Error> {
+
+    let table = mtree_table(tree, Some(depth))?;
+    let leaf_node = mtree_leaf_node(depth, leaf_index) as i64;
+    let leaf = mtree_get_nodes(&table, vec![leaf_node])[0];
+    let Some(root) = pgfr_mtree_get_root(tree)? else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!("merkle tree {tree} is not initialized")
+        );
+    };
+
+    let (path_indices, path_elements): (Vec<i64>, Vec<String>) = mtree_get_proof(&table, depth, leaf_index)
+        .into_iter()
+        .map(|(left_or_right, sibling)| (left_or_right, sibling.to_string()))
+        .unzip();
+
+    Ok(JsonB(json!({
+        "root": root.0.to_string(),
+        "leaf": leaf.to_string(),
+        "path_elements": path_elements,
+        "path_indices": path_indices,
+    })))
+}
+
+// Note: to_json / to_jsonb use the cast to json if any (e.g. for a pgfr[])
+#[pg_extern(immutable, strict, parallel_safe)]
+fn pgfr_to_json(input: PgFr) -> Json {
+    Json(Value::String(input.0.to_string()))
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn pgfr_to_jsonb(input: PgFr) -> JsonB {
+    JsonB(Value::String(input.0.to_string()))
+}
+
+extension_sql!(
+    r#"
+CREATE CAST (pgfr AS json) WITH FUNCTION pgfr_to_json(pgfr);
+CREATE CAST (pgfr AS jsonb) WITH FUNCTION pgfr_to_jsonb(pgfr);
+"#,
+    name = "pgfr_json_casts",
+    requires = [
+        pgfr_to_json,
+        pgfr_to_jsonb
+    ]
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use ark_bn254::Fr;
+    use merkle_core::proof::proof_root;
+    use super::*;
+    use crate::registry::DEFAULT_TREE;
+
+    #[pg_test]
+    fn test_mtree_get_proof_json() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[0, 7], ARRAY['2', '42']::pgfr[]);
+            "
+        ).unwrap();
+
+        let JsonB(proof) = pgfr_mtree_get_proof_json(3, 7, DEFAULT_TREE).unwrap();
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        assert_eq!(proof["root"], "9164054056146260648413073295070635933539618302378139976693739565479035405901");
+        assert_eq!(proof["leaf"], "42");
+        assert_eq!(proof["path_indices"], json!([1, 1, 1]));
+
+        let path: Vec<(i64, Fr)> = proof["path_indices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(proof["path_elements"].as_array().unwrap())
+            .map(|(index, element)| (index.as_i64().unwrap(), Fr::from_str(element.as_str().unwrap()).unwrap()))
+            .collect();
+        assert_eq!(
+            proof_root(Fr::from(42), &path).to_string(),
+            proof["root"].as_str().unwrap()
+        );
+    }
+
+    #[pg_test]
+    fn test_pgfr_to_jsonb() {
+        let value = Spi::get_one::<bool>("SELECT to_jsonb('42'::pgfr) = '\"42\"'::jsonb;").unwrap();
+        assert_eq!(value, Some(true));
+        let array = Spi::get_one::<bool>("SELECT to_jsonb(ARRAY['1', '2']::pgfr[]) = '[\"1\", \"2\"]'::jsonb;").unwrap();
+        assert_eq!(array, Some(true));
+        let object = Spi::get_one::<String>("SELECT json_build_object('leaf', '2'::pgfr)::text;").unwrap();
+        assert_eq!(object.as_deref(), Some("{\"leaf\" : \"2\"}"));
+        let cast = Spi::get_one::<bool>("SELECT '42'::pgfr::jsonb = '\"42\"'::jsonb;").unwrap();
+        assert_eq!(cast, Some(true));
+    }
+}
@@ -1,17 +1,30 @@
 mod merkle_tree;
-mod poseidon;
-mod merkle_tree_utils;
+mod sparse_storage;
+mod sparse_merkle_tree;
+mod indexed_merkle_tree;
+mod preimage;
+mod build;
+mod check;
+mod snapshot;
+mod guard;
+mod registry;
+mod stats;
+mod notify;
+mod changes;
+mod history;
+mod frontier_tree;
+mod json;
 
 // std
 use std::ffi::CStr;
 use std::str::FromStr;
 // third-party
 use ark_bn254::Fr;
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
-use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::CanonicalDeserialize;
+use ark_ff::{BigInt, PrimeField};
 // pgrx
 use pgrx::{
-    datum::Datum,
+    datum::{Datum, UnboxDatum},
     callconv::{ArgAbi, BoxRet},
     rust_regtypein,
     StringInfo,
@@ -24,6 +37,11 @@ use pgrx::{
 
 ::pgrx::pg_module_magic!(name, version);
 
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    guard::mtree_guard_init();
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy)]
 struct PgFr(Fr);
@@ -70,41 +88,85 @@ fn pgfr_out(pg_fr: PgFr) -> &'static CStr {
     unsafe { sb.leak_cstr() }
 }
 
-#[pg_extern(immutable, strict, parallel_safe)]
-fn pgfr_send(val: PgFr) -> Vec<u8> {
-    // warning!("pgfr_send");
-    let mut buffer = Vec::with_capacity(32);
-    match val.0.serialize_compressed(&mut buffer) {
-        Ok(()) => buffer,
-        Err(e) => {
-            ereport!(
-                ERROR,
-                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
-                format!("Failed to serialize Fr: {}", e)
-            );
-        }
+/// Fr as 32 bytes little endian (same bytes as ark serialize_compressed) without heap allocation
+fn fr_to_le_bytes(fr: &Fr) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, limb) in bytes.chunks_exact_mut(8).zip(fr.into_bigint().0) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
     }
+    bytes
 }
 
+/// Fr from 32 bytes little endian (None if the value is not < modulus)
+fn fr_from_le_bytes(bytes: &[u8; 32]) -> Option<Fr> {
+    let mut limbs = [0u64; 4];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+        // unwrap safe: chunks of 8 bytes
+        *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    Fr::from_bigint(BigInt::new(limbs))
+}
+
+/// pgfr as a bytea (32 bytes little endian), written directly in a palloc'd varlena (no intermediate Vec)
+struct PgFrBytes([u8; 32]);
+
+unsafe impl SqlTranslatable for PgFrBytes {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal("bytea"))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal("bytea")))
+    }
+}
+
+impl IntoDatum for PgFrBytes {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        let bytea = pgrx::rust_byte_slice_to_bytea(&self.0);
+        Some(pg_sys::Datum::from(bytea.into_pg()))
+    }
+
+    fn type_oid() -> Oid {
+        pg_sys::BYTEAOID
+    }
+}
+
+unsafe impl BoxRet for PgFrBytes {
+    unsafe fn box_into<'fcx>(self, fcinfo: &mut pgrx::callconv::FcInfo<'fcx>) -> Datum<'fcx> {
+        unsafe { fcinfo
+            .return_raw_datum( self.into_datum().unwrap() ) }
+    }
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn pgfr_send(val: PgFr) -> PgFrBytes {
+    PgFrBytes(fr_to_le_bytes(&val.0))
+}
+
+// Note: called for each value of a binary COPY and for each element of a pgfr[] (array_recv)
 #[pg_extern(immutable, strict, parallel_safe)]
 fn pgfr_recv(mut internal: ::pgrx::datum::Internal) -> PgFr {
 
-    // warning!("[pgfr_recv] internal: {:?}", internal.initialized());
     let buf = unsafe { internal.get_mut::<::pgrx::pg_sys::StringInfoData>().unwrap() };
-    buf.cursor = buf.len;
     let bytes = unsafe {
-        core::slice::from_raw_parts(buf.data as *const u8, buf.len as usize)
+        core::slice::from_raw_parts(buf.data.add(buf.cursor as usize) as *const u8, (buf.len - buf.cursor) as usize)
     };
-    match Fr::deserialize_compressed(bytes) {
-        Ok(fr) => PgFr(fr),
-        Err(e) => {
-            ereport!(
-                ERROR,
-                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
-                format!("Failed to deserialize Fr: {}", e)
-            );
-        }
-    }
+    let Ok(bytes) = <&[u8; 32]>::try_from(bytes) else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+            format!("Failed to deserialize Fr: expected 32 bytes, received {} bytes", bytes.len())
+        );
+    };
+    let Some(fr) = fr_from_le_bytes(bytes) else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+            "Failed to deserialize Fr: value is not lower than the field modulus"
+        );
+    };
+    buf.cursor = buf.len;
+    PgFr(fr)
 }
 
 impl FromDatum for PgFr {
@@ -116,11 +178,11 @@ impl FromDatum for PgFr {
         if is_null {
             None
         } else {
-            let ptr = datum.cast_mut_ptr::<u8>();
-            let bytes = std::slice::from_raw_parts(ptr, 32);
-            match Fr::deserialize_compressed(bytes) {
-                Ok(fr) => Some(PgFr(fr)),
-                Err(_) => {
+            // Note: a pgfr is stored as 32 bytes (fixed length type, no varlena header)
+            let bytes = &*datum.cast_mut_ptr::<[u8; 32]>();
+            match fr_from_le_bytes(bytes) {
+                Some(fr) => Some(PgFr(fr)),
+                None => {
                     error!("Failed to deserialize PgFr from disk storage");
                 }
             }
@@ -130,12 +192,11 @@ impl FromDatum for PgFr {
 
 impl IntoDatum for PgFr {
     fn into_datum(self) -> Option<pg_sys::Datum> {
-        // warning!("[PgFr] into_datum: {:?}", self);
-        // TODO / FIXME: should use serialized_compressed here?
-        let bytes = self.0.into_bigint().to_bytes_le();
+        // Note: same bytes as serialize_compressed (cf. pgfr_to_bytea)
+        let bytes = fr_to_le_bytes(&self.0);
         unsafe {
             let ptr = pg_sys::palloc(32);
-            std::ptr::copy_nonoverlapping(bytes.as_slice().as_ptr(), ptr as *mut u8, 32);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, 32);
             Some(pg_sys::Datum::from(ptr as usize))
         }
     }
@@ -164,6 +225,18 @@ unsafe impl BoxRet for PgFr {
     }
 }
 
+// Note: required to use pgfr[] as a function argument (e.g. Vec<PgFr>)
+unsafe impl UnboxDatum for PgFr {
+    type As<'src> = PgFr;
+
+    unsafe fn unbox<'src>(datum: Datum<'src>) -> Self::As<'src>
+    where
+        Self: 'src,
+    {
+        unsafe { Self::from_datum(datum.sans_lifetime(), false).unwrap() }
+    }
+}
+
 extension_sql!(
     r#"
 CREATE TYPE pgfr (
@@ -183,10 +256,8 @@ CREATE TYPE pgfr (
 );
 
 #[pg_extern(immutable, parallel_safe)]
-fn pgfr_to_bytea(input: PgFr) -> Vec<u8> {
-    let mut result = Vec::with_capacity(32);
-    input.0.serialize_compressed(&mut result).unwrap();
-    result
+fn pgfr_to_bytea(input: PgFr) -> PgFrBytes {
+    PgFrBytes(fr_to_le_bytes(&input.0))
 }
 
 #[pg_extern(immutable, parallel_safe)]
@@ -229,6 +300,8 @@ CREATE CAST (bytea AS pgfr) WITH FUNCTION bytea_to_pgfr(bytea) AS ASSIGNMENT;
 mod tests {
     use static_assertions::const_assert_eq;
     use std::ffi::c_void;
+    use ark_ff::BigInteger;
+    use ark_serialize::CanonicalSerialize;
     use super::*;
 
     const _: () = {
@@ -302,12 +375,94 @@ mod tests {
     fn test_pgfr_send() {
         let original_fr = Fr::from(42);
         let input = PgFr(original_fr);
-        let bytes = pgfr_send(input);
-        assert_eq!(bytes.len(), 32);
+        let bytes = pgfr_send(input).0;
         let deserialized = Fr::deserialize_compressed(&bytes[..]).unwrap();
         assert_eq!(deserialized, original_fr);
     }
 
+    #[pg_test]
+    fn test_pgfr_le_bytes() {
+        // Same bytes as ark serialize_compressed (cf. pgfr_to_bytea), including the largest value
+        let max = Fr::from(0) - Fr::from(1);
+        for fr in [Fr::from(0), Fr::from(42), Fr::from(u64::MAX), max] {
+            let mut expected = Vec::new();
+            fr.serialize_compressed(&mut expected).unwrap();
+            assert_eq!(fr_to_le_bytes(&fr).to_vec(), expected);
+            assert_eq!(fr_from_le_bytes(&fr_to_le_bytes(&fr)), Some(fr));
+        }
+        // The modulus is not a valid value
+        let mut modulus = [0u8; 32];
+        modulus.copy_from_slice(&Fr::MODULUS.to_bytes_le());
+        assert_eq!(fr_from_le_bytes(&modulus), None);
+    }
+
+    /// Server side file for a COPY test, removed when dropped (even if the test fails)
+    ///
+    /// Note: the name is unique per backend so that several test runs can run in parallel
+    struct CopyTestFile(String);
+
+    impl CopyTestFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("pg_merkle_tree_test_{name}_{}.copy", std::process::id()));
+            CopyTestFile(path.display().to_string())
+        }
+    }
+
+    impl Drop for CopyTestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[pg_test]
+    fn test_pgfr_binary_copy() {
+        let file = CopyTestFile::new("pgfr");
+        let path = &file.0;
+        // Note: the largest value is p - 1
+        Spi::run(&format!("
+            CREATE TABLE test_pgfr (my_index bigint, value pgfr, values pgfr[]);
+            INSERT INTO test_pgfr VALUES
+                (0, '2', ARRAY['1', '2', '3']::pgfr[]),
+                (1, '21888242871839275222246405745257275088548364400416034343698204186575808495616', ARRAY[]::pgfr[]),
+                (2, NULL, ARRAY['42', NULL]::pgfr[]);
+            INSERT INTO test_pgfr
+            SELECT i, i::text::pgfr, ARRAY[i::text, (i * 7)::text]::pgfr[] FROM generate_series(3, 1000) AS i;
+            COPY test_pgfr TO '{path}' (FORMAT binary);
+            CREATE TABLE test_pgfr_copy (LIKE test_pgfr);
+            COPY test_pgfr_copy FROM '{path}' (FORMAT binary);
+            "
+        )).unwrap();
+
+        // Note: pgfr has no comparison operator, values are compared as text
+        let mismatches = Spi::get_one::<i64>("
+            SELECT count(*)
+            FROM test_pgfr a
+            FULL JOIN test_pgfr_copy b ON a.my_index = b.my_index
+            WHERE a.value::text IS DISTINCT FROM b.value::text
+                OR a.values::text IS DISTINCT FROM b.values::text
+            "
+        ).unwrap().unwrap();
+        assert_eq!(mismatches, 0);
+        let count = Spi::get_one::<i64>("SELECT count(*) FROM test_pgfr_copy;").unwrap().unwrap();
+        assert_eq!(count, 1001);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "value is not lower than the field modulus")]
+    fn test_pgfr_binary_copy_invalid() {
+        let file = CopyTestFile::new("bytea");
+        let path = &file.0;
+        // A bytea column copied as a pgfr column: same binary format (32 bytes) but the value is the modulus
+        Spi::run(&format!("
+            CREATE TABLE test_bytea (value bytea);
+            INSERT INTO test_bytea VALUES ('\\x010000f093f5e1439170b97948e833285d588181b64550b829a031e1724e6430');
+            COPY test_bytea TO '{path}' (FORMAT binary);
+            CREATE TABLE test_pgfr (value pgfr);
+            COPY test_pgfr FROM '{path}' (FORMAT binary);
+            "
+        )).unwrap();
+    }
+
     #[pg_test]
     unsafe fn test_pgfr2_recv() {
 
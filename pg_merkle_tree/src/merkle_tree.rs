@@ -1,37 +1,44 @@
 // std
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 // third-party
 use ark_bn254::Fr;
-use ark_serialize::CanonicalSerialize;
+use merkle_core::{
+    default_hashes,
+    poseidon::poseidon_hash_,
+    merkle_tree_utils::{node_parent, first_child, leaf_node, multiproof_indexes},
+    proof::{MerkleProof, MultiProof},
+};
 // pgrx
 use pgrx::{
-    spi::{SpiClient, SpiResult},
+    spi::SpiResult,
     prelude::*,
     datum::DatumWithOid
 };
 use crate::PgFr;
-use crate::poseidon::poseidon_hash_;
-use crate::merkle_tree_utils::{node_parent, first_child};
+use crate::guard::MtreeWriteGuard;
+use crate::registry::{mtree_lock, mtree_registered_depth, mtree_table, mtree_touch};
 
 #[pg_extern(parallel_unsafe)]
-fn pgfr_mtree_init(depth: i64) {
+fn pgfr_mtree_init(depth: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<(), pgrx::spi::Error> {
+    mtree_init(tree, depth as i16)
+}
+
+pub(crate) fn mtree_init(tree: &str, depth: i16) -> SpiResult<()> {
+    let _guard = MtreeWriteGuard::new();
 
+    let table = mtree_table(tree, Some(depth))?;
+    mtree_lock(tree)?;
     let depth = depth as usize;
 
     // Note: init the merkle tree as 1 hash / level of the tree
     //       so we can insert into the tree with only a few queries
-    let mut level_hashes = Vec::with_capacity(depth + 1);
-    level_hashes.push(Fr::default()); // set the initial leaf value
-    // Compute hash from the initial leaf value up to the root node
-    (0..depth).for_each(|level_index| {
-        level_hashes.push(poseidon_hash_(&[level_hashes[level_index]; 2]))
-    });
+    let level_hashes = default_hashes(depth);
 
-    let query = r#"
-        INSERT INTO pgfr_mtree (index_in_mtree, value)
+    let query = format!(r#"
+        INSERT INTO {table} (index_in_mtree, value)
         SELECT i, $1
         FROM generate_series($2, $3) as i
-    "#;
+    "#);
 
     Spi::connect_mut(|client| {
 
@@ -40,7 +47,7 @@ fn pgfr_mtree_init(depth: i64) {
             let level_end_index = (1i64 << (level + 1)) - 2;
 
             client.update(
-                query,
+                &query,
                 None,
                 &[
                     // $1: The hash value for this entire level
@@ -50,16 +57,20 @@ fn pgfr_mtree_init(depth: i64) {
                     // $3: End Index
                     level_end_index.into(),
                 ]
-            ).expect(format!("Failed to insert hash {hash} at level {level}").as_str());
+            )?;
         }
-    });
+        Ok::<_, pgrx::spi::Error>(())
+    })?;
+
+    mtree_touch(tree, Vec::new())
 }
 
 #[pg_extern(stable, strict, parallel_safe)]
-fn pgfr_mtree_get_root() -> Result<Option<PgFr>, pgrx::spi::Error> {
+pub(crate) fn pgfr_mtree_get_root(tree: default!(&str, "'pgfr_mtree'")) -> Result<Option<PgFr>, pgrx::spi::Error> {
 
+    let table = mtree_table(tree, None)?;
     let res: SpiResult<Option<PgFr>> = Spi::get_one_with_args(
-        "SELECT value::pgfr FROM pgfr_mtree WHERE index_in_mtree = 0 LIMIT 1;",
+        &format!("SELECT value::pgfr FROM {table} WHERE index_in_mtree = 0 LIMIT 1;"),
         &[]
     );
 
@@ -67,114 +78,238 @@ fn pgfr_mtree_get_root() -> Result<Option<PgFr>, pgrx::spi::Error> {
 }
 
 #[pg_extern(parallel_unsafe)]
-fn pgfr_mtree_set_leaf(depth: i16, index_in_mtree: i64, leaf_value: PgFr) -> Result<(), pgrx::spi::Error> {
+pub(crate) fn pgfr_mtree_set_leaf(
+    depth: i16,
+    index_in_mtree: i64,
+    leaf_value: PgFr,
+    tree: default!(&str, "'pgfr_mtree'"),
+) -> Result<(), pgrx::spi::Error> {
 
     // TODO: rename index_in_mtree to leaf_index ?_index ?
 
-    let index = index_in_mtree as usize;
-    let leaf_index_ = (1 << depth) + index - 1;
-    let leaf_index = leaf_index_ as i64;
+    pgfr_mtree_set_leaves(depth, vec![index_in_mtree], vec![leaf_value], tree)
+}
 
-    let query = "UPDATE pgfr_mtree SET value = $1 WHERE index_in_mtree = $2";
+/// Set multiple leaves at once (if a leaf index is given multiple times, the last value is used)
+#[pg_extern(strict, parallel_unsafe)]
+pub(crate) fn pgfr_mtree_set_leaves(
+    depth: i16,
+    indices: Vec<i64>,
+    leaf_values: Vec<PgFr>,
+    tree: default!(&str, "'pgfr_mtree'"),
+) -> Result<(), pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    let table = mtree_table(tree, Some(depth))?;
+    mtree_lock(tree)?;
+
+    if indices.len() != leaf_values.len() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("Received {} leaf indices but {} leaf values", indices.len(), leaf_values.len())
+        );
+    }
 
+    let mut to_update = BTreeMap::new();
+    for (index, leaf_value) in indices.iter().zip(leaf_values) {
+        to_update.insert(mtree_leaf_node(depth, *index) as i64, leaf_value);
+    }
+
+    // Note: the previous leaf values are only needed for the change log of the registered trees
+    let changes = if mtree_registered_depth(tree)?.is_some() {
+        let leaf_nodes: Vec<i64> = to_update.keys().copied().collect();
+        let first_leaf = (1i64 << depth) - 1;
+        mtree_get_nodes(&table, leaf_nodes.clone())
+            .into_iter()
+            .zip(leaf_nodes)
+            .map(|(old_value, node)| (node - first_leaf, old_value, to_update[&node].0))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Get index and new hashes to insert in tree after leaves update
+    let leaf_nodes = to_update.keys().map(|index| *index as usize).collect();
+    mtree_get_hashes(&table, leaf_nodes, &mut to_update);
+
+    // The leaves are not computed from a preimage (anymore)
     Spi::run_with_args(
-        query,
-        &[
-            unsafe { DatumWithOid::new(leaf_value, PgFr::type_oid()) },
-            leaf_index.into()
-        ]
+        "DELETE FROM pgfr_mtree_preimage WHERE tree = $1 AND leaf_index = ANY($2)",
+        &[tree.into(), indices.into()]
     )?;
 
-    // Get index and new hashes to insert in tree after leaf update
-    let mut to_update = BTreeMap::new();
-    Spi::connect(|client| {
-        mtree_get_hashes(client, leaf_index_, leaf_index_, &mut to_update);
-    });
-
     let (to_update_indexes, to_update_values): (Vec<i64>, Vec<PgFr>) = to_update.into_iter().unzip();
 
-    let query_2 = r#"
-        UPDATE pgfr_mtree
+    let query = format!(r#"
+        UPDATE {table} AS m
         SET value = data.new_value
         FROM (
             SELECT * FROM UNNEST($1::bigint[], $2::pgfr[])
             AS t(i_index, new_value)
         ) AS data
-        WHERE pgfr_mtree.index_in_mtree = data.i_index;
-        "#;
+        WHERE m.index_in_mtree = data.i_index;
+        "#);
 
-    Spi::run_with_args(query_2,
+    Spi::run_with_args(&query,
                        &[
                            to_update_indexes.into(),
                            to_update_values.into()
                        ]
     )?;
 
-    Ok(())
+    mtree_touch(tree, changes)
 }
 
-fn mtree_get_hashes(client: &SpiClient, start_index: usize, end_index: usize, to_update: &mut BTreeMap<i64, PgFr>) {
-
-    let query_1 = "SELECT value::pgfr FROM pgfr_mtree WHERE index_in_mtree = $1 LIMIT 1";
-
-    let mut start_index = start_index;
-    let mut end_index = end_index;
+// Keep a merkle tree in sync with an application table (statement level triggers + pgfr_mtree_set_leaves)
+// Note: the leaf of a deleted row (or of a row moved to another index) is reset to the empty leaf (0)
+//       TRUNCATE on the source table is not handled
+extension_sql!(
+    r#"
+CREATE FUNCTION pgfr_mtree_sync_trigger() RETURNS trigger
+LANGUAGE plpgsql AS $$
+DECLARE
+    value_column name := TG_ARGV[0];
+    index_column name := TG_ARGV[1];
+    depth smallint := TG_ARGV[2]::smallint;
+    tree text := TG_ARGV[3];
+    leaves text;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        leaves := format(
+            'SELECT %1$I AS i, coalesce(%2$I, ''0''::pgfr) AS v, 1 AS ord FROM pgfr_mtree_new_rows',
+            index_column, value_column
+        );
+    ELSIF TG_OP = 'UPDATE' THEN
+        leaves := format(
+            'SELECT %1$I AS i, ''0''::pgfr AS v, 0 AS ord FROM pgfr_mtree_old_rows
+             UNION ALL
+             SELECT %1$I, coalesce(%2$I, ''0''::pgfr), 1 FROM pgfr_mtree_new_rows',
+            index_column, value_column
+        );
+    ELSE
+        leaves := format(
+            'SELECT %1$I AS i, ''0''::pgfr AS v, 0 AS ord FROM pgfr_mtree_old_rows',
+            index_column
+        );
+    END IF;
+
+    -- Note: set_leaves uses the last value for a given index so old leaves are reset first
+    --       (same sort key in both arrays so that indices and values stay aligned)
+    EXECUTE format(
+        'SELECT pgfr_mtree_set_leaves($1, array_agg(i ORDER BY ord, i), array_agg(v ORDER BY ord, i), $2)
+         FROM (%s) AS leaves WHERE i IS NOT NULL',
+        leaves
+    ) USING depth, tree;
+
+    RETURN NULL;
+END;
+$$;
+
+CREATE FUNCTION pgfr_mtree_attach(
+    source regclass, value_column name, index_column name, depth smallint, tree text DEFAULT 'pgfr_mtree'
+) RETURNS void
+LANGUAGE plpgsql AS $$
+BEGIN
+    EXECUTE format(
+        'CREATE TRIGGER pgfr_mtree_sync_insert AFTER INSERT ON %s
+         REFERENCING NEW TABLE AS pgfr_mtree_new_rows
+         FOR EACH STATEMENT EXECUTE FUNCTION pgfr_mtree_sync_trigger(%L, %L, %L, %L)',
+        source, value_column, index_column, depth, tree
+    );
+    EXECUTE format(
+        'CREATE TRIGGER pgfr_mtree_sync_update AFTER UPDATE ON %s
+         REFERENCING OLD TABLE AS pgfr_mtree_old_rows NEW TABLE AS pgfr_mtree_new_rows
+         FOR EACH STATEMENT EXECUTE FUNCTION pgfr_mtree_sync_trigger(%L, %L, %L, %L)',
+        source, value_column, index_column, depth, tree
+    );
+    EXECUTE format(
+        'CREATE TRIGGER pgfr_mtree_sync_delete AFTER DELETE ON %s
+         REFERENCING OLD TABLE AS pgfr_mtree_old_rows
+         FOR EACH STATEMENT EXECUTE FUNCTION pgfr_mtree_sync_trigger(%L, %L, %L, %L)',
+        source, value_column, index_column, depth, tree
+    );
 
-    while let (Some(start_parent), Some(end_parent)) = (node_parent(start_index), node_parent(end_index)) {
+    -- Initial sync with the rows already in the source table
+    EXECUTE format(
+        'SELECT pgfr_mtree_set_leaves($1, array_agg(%1$I), array_agg(coalesce(%2$I, ''0''::pgfr)), $2)
+         FROM %3$s WHERE %1$I IS NOT NULL',
+        index_column, value_column, source
+    ) USING depth, tree;
+END;
+$$;
+
+CREATE FUNCTION pgfr_mtree_detach(source regclass) RETURNS void
+LANGUAGE plpgsql AS $$
+BEGIN
+    EXECUTE format('DROP TRIGGER IF EXISTS pgfr_mtree_sync_insert ON %s', source);
+    EXECUTE format('DROP TRIGGER IF EXISTS pgfr_mtree_sync_update ON %s', source);
+    EXECUTE format('DROP TRIGGER IF EXISTS pgfr_mtree_sync_delete ON %s', source);
+END;
+$$;
+"#,
+    name = "pgfr_mtree_sync_triggers",
+    requires = [pgfr_mtree_set_leaves]
+);
+
+pub(crate) fn mtree_get_hashes(table: &str, nodes: BTreeSet<usize>, to_update: &mut BTreeMap<i64, PgFr>) {
+
+    let mut level = nodes;
+
+    // Loop until we reach the merkle tree root node (which has no parent)
+    while !level.is_empty() && !level.contains(&0) {
+
+        let parents: BTreeSet<usize> = level
+            .iter()
+            .filter_map(|index| node_parent(*index))
+            .collect();
+
+        // Children that are not updated are read from the db (1 query per level)
+        let to_read: Vec<i64> = parents
+            .iter()
+            .flat_map(|parent| {
+                let left_child = first_child(*parent) as i64;
+                [left_child, left_child + 1]
+            })
+            .filter(|index| !to_update.contains_key(index))
+            .collect();
+        let values = mtree_get_nodes(table, to_read.clone());
+        let read_values: BTreeMap<i64, Fr> = to_read.into_iter().zip(values).collect();
 
-        for parent in start_parent..=end_parent {
+        for parent in parents.iter() {
 
             // iter over parent nodes - for each parent, get left child and right child 'value' column
-            let left_child_ = first_child(parent);
-            let right_child_ = left_child_ + 1;
-
-            let left_child = left_child_ as i64;
-            let right_child = right_child_ as i64;
-
-            // Get value for left child
-            let left_child_value = if to_update.contains_key(&left_child) {
-                to_update[&left_child]
-            } else {
-                let left_child_value_ = client.select(query_1, None, &[left_child.into()]);
-                let left_child_value = left_child_value_
-                    .unwrap() // unwrap safe: assume merkle tree table has been correctly initialized
-                    .first() // SELECT query only returns 1 element
-                    .get_one::<PgFr>() // SELECT query returns only column 'value'
-                    .unwrap()// unwrap safe: SELECT query returns only column 'value'
-                    .unwrap(); // unwrap safe: assume 'value' column is always initialized
-                left_child_value
-            };
-
-            // Get value for right child
-            let right_child_value = if to_update.contains_key(&right_child) {
-                to_update[&right_child]
-            } else {
-                let right_child_value_ = client.select(query_1, None, &[right_child.into()]);
-                let right_child_value = right_child_value_
-                    .unwrap()
-                    .first()
-                    .get_one::<PgFr>()
-                    .unwrap()
-                    .unwrap();
-                right_child_value
+            let left_child = first_child(*parent) as i64;
+            let right_child = left_child + 1;
+
+            let child_value = |child: i64| {
+                to_update
+                    .get(&child)
+                    .map(|value| value.0)
+                    .unwrap_or_else(|| read_values[&child])
             };
 
             // Compute hash
-            let value = poseidon_hash_(&[left_child_value.0, right_child_value.0]);
-            let parent_ = parent as i64;
+            let value = poseidon_hash_(&[child_value(left_child), child_value(right_child)]);
 
             // Store it in our hashmap (db will be updated later in bulk)
-            to_update.insert(parent_, PgFr(value));
-
-            // Loop until we reach the merkle tree root node (which has no parent)
-            start_index = start_parent;
-            end_index = end_parent;
+            to_update.insert(*parent as i64, PgFr(value));
         }
+
+        level = parents;
     }
 }
 
 #[pg_extern(stable, strict, parallel_safe)]
-fn pgfr_mtree_get_proof(depth: i16, leaf_index: i64) -> Vec<u8> {
+fn pgfr_mtree_get_proof(depth: i16, leaf_index: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let table = mtree_table(tree, Some(depth))?;
+    let path = mtree_get_proof(&table, depth, leaf_index);
+    Ok(MerkleProof { path }.to_bytes())
+}
+
+/// Proof path of a leaf: (0 if the node is a left child else 1, sibling value), from the leaf to the root
+pub(crate) fn mtree_get_proof(table: &str, depth: i16, leaf_index: i64) -> Vec<(i64, Fr)> {
 
     let leaf_index_ = leaf_index as usize;
     // TODO: rename to leaf_index or node_index ?
@@ -206,26 +341,43 @@ fn pgfr_mtree_get_proof(depth: i16, leaf_index: i64) -> Vec<u8> {
         index = parent
     }
 
+    let values = mtree_get_nodes(table, mtree_indexes);
+
+    let proof_data: Vec<(i64, Fr)> = left_or_right
+        .iter()
+        .zip(values)
+        .map(|(i, values)| {
+            (*i, values)
+        })
+        .collect();
+
+    // info!("proof_data: {:?}", proof_data);
+
+    proof_data
+}
+
+pub(crate) fn mtree_get_nodes(table: &str, mtree_indexes: Vec<i64>) -> Vec<Fr> {
+
     let mtree_indexes_len = mtree_indexes.len();
 
     // Note: Using JOIN implicitly assumes that all nodes & leaves are initialized in the DB
     //       This is doubled-checked after the query
     // Note 2: UNNEST Expands an array into a set of rows. The array's elements are read out in storage order.
     //         So using WITH ORDINALITY we can return the SELECT in the array order
-    let query = r#"
+    let query = format!(r#"
         SELECT m.value
         FROM UNNEST($1::bigint[]) WITH ORDINALITY AS t(req_idx, ord)
-        JOIN pgfr_mtree m
+        JOIN {table} m
             ON m.index_in_mtree = t.req_idx
         ORDER BY t.ord ASC
-    "#;
+    "#);
 
     let oid = PgBuiltInOids::INT8ARRAYOID.oid();
 
     let values = Spi::connect(|client| {
 
         let result = client.select(
-            query,
+            &query,
             None,
             &[
                 unsafe { DatumWithOid::new(mtree_indexes, oid.value()) },
@@ -250,19 +402,118 @@ fn pgfr_mtree_get_proof(depth: i16, leaf_index: i64) -> Vec<u8> {
                mtree_indexes_len, values.len());
     }
 
-    let proof_data: Vec<(i64, Fr)> = left_or_right
+    values
+}
+
+// Condition on the rows of a tree table for the leaves that are not empty ($1: depth of the tree)
+// Note: pgfr has no comparison operator, an empty leaf is 32 zero bytes
+const MTREE_NON_EMPTY_LEAF: &str =
+    "index_in_mtree >= (1::bigint << $1) - 1 AND pgfr_to_bytea(value) <> decode(repeat('00', 32), 'hex')";
+
+/// The leaves that are not empty (leaf_index, value), sorted by leaf index
+pub(crate) fn mtree_leaves(table: &str, depth: i16) -> SpiResult<Vec<(i64, Fr)>> {
+
+    let query = format!(r#"
+        SELECT index_in_mtree - ((1::bigint << $1) - 1), value
+        FROM {table}
+        WHERE {MTREE_NON_EMPTY_LEAF}
+        ORDER BY index_in_mtree
+    "#);
+
+    Spi::connect(|client| {
+        let mut leaves = Vec::new();
+        for row in client.select(&query, None, &[i32::from(depth).into()])? {
+            let leaf_index = row.get::<i64>(1)?.expect("leaf_index is not null");
+            let value = row.get::<PgFr>(2)?.expect("value is not null");
+            leaves.push((leaf_index, value.0));
+        }
+        Ok(leaves)
+    })
+}
+
+/// Number of leaves that are not empty and index following the last one (0 if all the leaves are empty)
+pub(crate) fn mtree_leaf_stats(table: &str, depth: i16) -> SpiResult<(i64, i64)> {
+    let (leaves, next_index) = Spi::get_two_with_args::<i64, i64>(
+        &format!(r#"
+            SELECT count(*), coalesce(max(index_in_mtree) - ((1::bigint << $1) - 1) + 1, 0)
+            FROM {table}
+            WHERE {MTREE_NON_EMPTY_LEAF}
+        "#),
+        &[i32::from(depth).into()]
+    )?;
+    Ok((leaves.unwrap_or(0), next_index.unwrap_or(0)))
+}
+
+pub(crate) fn mtree_leaf_node(depth: i16, leaf_index: i64) -> usize {
+    if leaf_index < 0 || leaf_index >= (1i64 << depth) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("leaf index {leaf_index} is out of range for a merkle tree of depth {depth}")
+        );
+    }
+    leaf_node(depth as usize, leaf_index as usize)
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_get_multiproof(depth: i16, leaf_indices: Vec<i64>, tree: default!(&str, "'pgfr_mtree'")) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let table = mtree_table(tree, Some(depth))?;
+
+    let leaf_nodes: Vec<usize> = leaf_indices
         .iter()
+        .map(|leaf_index| mtree_leaf_node(depth, *leaf_index))
+        .collect();
+
+    // Only the siblings that cannot be computed from the requested leaves are returned
+    let mtree_indexes: Vec<i64> = multiproof_indexes(&leaf_nodes)
+        .into_iter()
+        .map(|index| index as i64)
+        .collect();
+
+    let values = mtree_get_nodes(&table, mtree_indexes.clone());
+
+    // Note: the node index is stored alongside each value so the verifier can check the layout
+    let proof_data: Vec<(i64, Fr)> = mtree_indexes
+        .into_iter()
         .zip(values)
-        .map(|(i, values)| {
-            (*i, values)
-        })
         .collect();
 
-    // info!("proof_data: {:?}", proof_data);
+    Ok(MultiProof { nodes: proof_data }.to_bytes())
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn pgfr_mtree_verify_multiproof(depth: i16, leaf_indices: Vec<i64>, leaf_values: Vec<PgFr>, proof: &[u8], root: PgFr) -> bool {
+
+    if leaf_indices.len() != leaf_values.len() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("Received {} leaf indices but {} leaf values", leaf_indices.len(), leaf_values.len())
+        );
+    }
 
-    let mut buffer = Vec::new();
-    proof_data.serialize_compressed(&mut buffer).expect("Serialization failed");
-    buffer
+    let multiproof = match MultiProof::from_bytes(proof) {
+        Ok(multiproof) => multiproof,
+        Err(e) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+                format!("Invalid encoding for multiproof: {}", e)
+            );
+        }
+    };
+
+    let mut leaf_nodes = BTreeMap::new();
+    for (leaf_index, leaf_value) in leaf_indices.iter().zip(leaf_values) {
+        let node = mtree_leaf_node(depth, *leaf_index);
+        // The same leaf cannot have 2 different values
+        if leaf_nodes.insert(node, leaf_value.0).is_some_and(|v| v != leaf_value.0) {
+            return false;
+        }
+    }
+
+    multiproof.compute_root(leaf_nodes) == Some(root.0)
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -272,6 +523,7 @@ mod tests {
     use std::str::FromStr;
     use ark_serialize::CanonicalDeserialize;
     use super::*;
+    use crate::registry::DEFAULT_TREE;
 
     #[pg_test]
     fn test_merkle_tree_init() {
@@ -282,7 +534,7 @@ mod tests {
             "
         );
 
-        pgfr_mtree_init(3);
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
 
         // Get root manually
         let root_node = Spi::get_one::<PgFr>("SELECT value FROM pgfr_mtree WHERE index_in_mtree = 0;").unwrap().unwrap();
@@ -298,12 +550,12 @@ mod tests {
             "
         );
 
-        pgfr_mtree_init(3);
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
 
         // Get root manually
         let root_node = Spi::get_one::<PgFr>("SELECT value FROM pgfr_mtree WHERE index_in_mtree = 0;").unwrap().unwrap();
 
-        let root_node_2 = pgfr_mtree_get_root().unwrap().unwrap();
+        let root_node_2 = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
 
         assert_eq!(root_node.0, Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap());
         assert_eq!(root_node.0, root_node_2.0);
@@ -316,14 +568,14 @@ mod tests {
             CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
             "
         );
-        pgfr_mtree_init(3);
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
 
-        pgfr_mtree_set_leaf(3, 0, PgFr(Fr::from(2))).unwrap();
-        let root = pgfr_mtree_get_root().unwrap().unwrap();
+        pgfr_mtree_set_leaf(3, 0, PgFr(Fr::from(2)), DEFAULT_TREE).unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
         assert_eq!(root.0, Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap());
 
-        pgfr_mtree_set_leaf(3, 7, PgFr(Fr::from(42))).unwrap();
-        let root = pgfr_mtree_get_root().unwrap().unwrap();
+        pgfr_mtree_set_leaf(3, 7, PgFr(Fr::from(42)), DEFAULT_TREE).unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
         assert_eq!(root.0, Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap());
     }
 
@@ -335,10 +587,10 @@ mod tests {
             "
         );
 
-        pgfr_mtree_init(3);
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
 
         {
-            let proof_bytes = pgfr_mtree_get_proof(3, 0);
+            let proof_bytes = pgfr_mtree_get_proof(3, 0, DEFAULT_TREE).unwrap();
             let proof = Vec::<(i64, Fr)>::deserialize_compressed(proof_bytes.as_slice()).unwrap();
             assert_eq!(
                 proof,
@@ -349,7 +601,7 @@ mod tests {
                 ]);
         }
         {
-            let proof_bytes = pgfr_mtree_get_proof(3, 1);
+            let proof_bytes = pgfr_mtree_get_proof(3, 1, DEFAULT_TREE).unwrap();
             let proof = Vec::<(i64, Fr)>::deserialize_compressed(proof_bytes.as_slice()).unwrap();
             assert_eq!(
                 proof,
@@ -360,7 +612,7 @@ mod tests {
                 ]);
         }
         {
-            let proof_bytes = pgfr_mtree_get_proof(3, 7);
+            let proof_bytes = pgfr_mtree_get_proof(3, 7, DEFAULT_TREE).unwrap();
             let proof = Vec::<(i64, Fr)>::deserialize_compressed(proof_bytes.as_slice()).unwrap();
 
             assert_eq!(
@@ -372,5 +624,123 @@ mod tests {
                 ]);
         }
     }
-}
 
+    #[pg_test]
+    fn test_pgfr_get_multiproof() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            "
+        );
+
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
+
+        // Leaves 0 & 1 are siblings, so only 1 node per level is required for them
+        let proof_bytes = pgfr_mtree_get_multiproof(3, vec![7, 0, 1], DEFAULT_TREE).unwrap();
+        let proof = Vec::<(i64, Fr)>::deserialize_compressed(proof_bytes.as_slice()).unwrap();
+        assert_eq!(
+            proof,
+            vec![
+                (13, Fr::from(0)),
+                (4, Fr::from_str("14744269619966411208579211824598458697587494354926760081771325075741142829156").unwrap()),
+                (5, Fr::from_str("14744269619966411208579211824598458697587494354926760081771325075741142829156").unwrap()),
+            ]);
+    }
+
+    #[pg_test]
+    fn test_pgfr_verify_multiproof() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            "
+        );
+
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
+        pgfr_mtree_set_leaf(3, 0, PgFr(Fr::from(2)), DEFAULT_TREE).unwrap();
+        pgfr_mtree_set_leaf(3, 7, PgFr(Fr::from(42)), DEFAULT_TREE).unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+
+        let leaf_indices = vec![0, 3, 7];
+        let leaf_values = vec![PgFr(Fr::from(2)), PgFr(Fr::from(0)), PgFr(Fr::from(42))];
+        let proof = pgfr_mtree_get_multiproof(3, leaf_indices.clone(), DEFAULT_TREE).unwrap();
+
+        assert!(pgfr_mtree_verify_multiproof(3, leaf_indices.clone(), leaf_values.clone(), &proof, root));
+        // Wrong root
+        assert!(!pgfr_mtree_verify_multiproof(3, leaf_indices.clone(), leaf_values, &proof, PgFr(Fr::from(1))));
+        // Wrong leaf value
+        let leaf_values = vec![PgFr(Fr::from(2)), PgFr(Fr::from(1)), PgFr(Fr::from(42))];
+        assert!(!pgfr_mtree_verify_multiproof(3, leaf_indices, leaf_values, &proof, root));
+        // Proof for other leaves
+        let leaf_values = vec![PgFr(Fr::from(2)), PgFr(Fr::from(0))];
+        assert!(!pgfr_mtree_verify_multiproof(3, vec![0, 2], leaf_values, &proof, root));
+    }
+
+    #[pg_test]
+    fn test_pgfr_set_leaves() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            "
+        );
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
+
+        // Same leaves as test_pgfr_set_leaf (+ leaf 1 overwritten by the last value)
+        pgfr_mtree_set_leaves(
+            3,
+            vec![0, 1, 7, 1],
+            vec![PgFr(Fr::from(2)), PgFr(Fr::from(5)), PgFr(Fr::from(42)), PgFr(Fr::from(0))],
+            DEFAULT_TREE
+        ).unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap());
+    }
+
+    #[pg_test]
+    fn test_pgfr_mtree_attach() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            CREATE TABLE members (id bigint, commitment pgfr, leaf_index bigint);
+            INSERT INTO members VALUES (1, '2', 0);
+            "
+        );
+        pgfr_mtree_init(3, DEFAULT_TREE).unwrap();
+        let empty_root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+
+        // Existing rows are synced when attaching
+        Spi::run("SELECT pgfr_mtree_attach('members', 'commitment', 'leaf_index', 3::smallint);").unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap());
+
+        Spi::run("INSERT INTO members VALUES (2, '42', 7), (3, '43', 6);").unwrap();
+        Spi::run("DELETE FROM members WHERE id = 3;").unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap());
+
+        // Moving a member to another leaf resets its previous leaf
+        Spi::run("UPDATE members SET leaf_index = 5 WHERE id = 2;").unwrap();
+        Spi::run("UPDATE members SET leaf_index = 7 WHERE id = 2;").unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap());
+
+        // Several rows in one statement: leaves 6 and 7 are swapped twice
+        Spi::run("
+            INSERT INTO members VALUES (3, '43', 6);
+            UPDATE members SET leaf_index = 13 - leaf_index WHERE id IN (2, 3);
+            UPDATE members SET leaf_index = 13 - leaf_index WHERE id IN (2, 3);
+            DELETE FROM members WHERE id = 3;
+            "
+        ).unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap());
+
+        Spi::run("DELETE FROM members;").unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, empty_root.0);
+
+        // No more sync once detached
+        Spi::run("SELECT pgfr_mtree_detach('members'); INSERT INTO members VALUES (1, '2', 0);").unwrap();
+        let root = pgfr_mtree_get_root(DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(root.0, empty_root.0);
+    }
+}
@@ -0,0 +1,115 @@
+// third-party
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::guard::MtreeWriteGuard;
+use crate::registry::mtree_registered_depth;
+
+/// Root as a 0x prefixed big endian hex string (as expected by on-chain contracts)
+fn root_hex(root: Fr) -> String {
+    let hex: String = root
+        .into_bigint()
+        .to_bytes_be()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("0x{hex}")
+}
+
+/// Notify the channel of a tree with its new root
+///
+/// Note: as any NOTIFY, the notification is only delivered if the transaction commits
+pub(crate) fn mtree_notify(channel: &str, tree_id: i32, tree: &str, root_seq: i64, root: Fr) -> SpiResult<()> {
+    Spi::run_with_args(
+        r#"
+        SELECT pg_notify(
+            $1,
+            json_build_object('tree_id', $2, 'tree', $3, 'root', $4, 'seq', $5)::text
+        )
+        "#,
+        &[channel.into(), tree_id.into(), tree.into(), root_hex(root).into(), root_seq.into()]
+    )
+}
+
+/// Notify the given channel (NULL to disable the notifications) each time the root of a tree changes.
+/// The payload is a json object: {"tree_id": 1, "tree": "members_tree", "root": "0x...", "seq": 42}
+///
+/// e.g. SELECT pgfr_mtree_set_notify('members_tree', 'members_root'); then LISTEN members_root;
+///
+/// Note: only the trees created with pgfr_mtree_create can be notified
+#[pg_extern(parallel_unsafe)]
+fn pgfr_mtree_set_notify(tree: &str, channel: Option<&str>) -> Result<(), pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    if mtree_registered_depth(tree)?.is_none() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("merkle tree {tree} is not registered (cf. pgfr_mtree_create)")
+        );
+    }
+
+    Spi::run_with_args(
+        "UPDATE pgfr_mtree_registry SET notify_channel = $2 WHERE name = $1",
+        &[tree.into(), channel.into()]
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::pgfr_mtree_get_root;
+
+    // Note: a notification is only delivered on commit (a pg_test is rolled back),
+    //       cf. pg_merkle_tree_client/tests/notify.rs for the delivery
+    #[pg_test]
+    fn test_mtree_notify() {
+        let _res = Spi::run("SELECT pgfr_mtree_create('members_tree', 3::smallint);").unwrap();
+        pgfr_mtree_set_notify("members_tree", Some("members_root")).unwrap();
+        let channel = Spi::get_one::<String>(
+            "SELECT notify_channel FROM pgfr_mtree_registry WHERE name = 'members_tree';"
+        ).unwrap();
+        assert_eq!(channel.as_deref(), Some("members_root"));
+
+        Spi::run("
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2', 'members_tree');
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[7], ARRAY['42']::pgfr[], 'members_tree');
+            "
+        ).unwrap();
+
+        // pgfr_mtree_create + 2 modifications
+        let root_seq = Spi::get_one::<i64>(
+            "SELECT root_seq FROM pgfr_mtree_registry WHERE name = 'members_tree';"
+        ).unwrap().unwrap();
+        assert_eq!(root_seq, 3);
+
+        // Same root as zerokit_ref (leaf 0 = 2, leaf 7 = 42)
+        let root_hex = root_hex(pgfr_mtree_get_root("members_tree").unwrap().unwrap().0);
+        let root_bytes: Vec<u8> = (2..root_hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&root_hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(
+            Fr::from_be_bytes_mod_order(&root_bytes).to_string(),
+            "9164054056146260648413073295070635933539618302378139976693739565479035405901"
+        );
+
+        pgfr_mtree_set_notify("members_tree", None).unwrap();
+        let channel = Spi::get_one::<String>(
+            "SELECT notify_channel FROM pgfr_mtree_registry WHERE name = 'members_tree';"
+        ).unwrap();
+        assert!(channel.is_none());
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "merkle tree pgfr_mtree is not registered")]
+    fn test_mtree_notify_unregistered() {
+        pgfr_mtree_set_notify("pgfr_mtree", Some("members_root")).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+// third-party
+use ark_bn254::Fr;
+use merkle_core::poseidon::poseidon_hash;
+// pgrx
+use pgrx::{
+    datum::VariadicArray,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::merkle_tree::pgfr_mtree_set_leaf;
+use crate::registry::{mtree_table, DEFAULT_TREE};
+
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_mtree_preimage (
+    tree text NOT NULL,
+    leaf_index bigint NOT NULL,
+    preimage pgfr[] NOT NULL,
+    PRIMARY KEY (tree, leaf_index)
+);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_mtree_preimage', '');
+"#,
+    name = "create_preimage_table",
+    requires = ["create_pgfr_type"]
+);
+
+fn preimage_hash(preimage: &[Fr]) -> Fr {
+    match poseidon_hash(preimage) {
+        Ok(hash) => hash,
+        Err(e) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("Cannot hash a preimage of {} elements: {}", preimage.len(), e)
+            );
+        }
+    }
+}
+
+/// Set a leaf to the Poseidon hash of the given values and store these values. Returns the leaf value
+#[pg_extern(parallel_unsafe)]
+fn pgfr_mtree_set_leaf_preimage(depth: i16, index_in_mtree: i64, preimage: VariadicArray<'_, PgFr>) -> Result<PgFr, pgrx::spi::Error> {
+    mtree_set_leaf_preimage(DEFAULT_TREE, depth, index_in_mtree, preimage)
+}
+
+/// Same as pgfr_mtree_set_leaf_preimage for the given tree
+/// Note: the tree is the last argument before the VARIADIC argument (which must be the last one)
+#[pg_extern(parallel_unsafe)]
+fn pgfr_mtree_set_leaf_preimage_in(depth: i16, index_in_mtree: i64, tree: &str, preimage: VariadicArray<'_, PgFr>) -> Result<PgFr, pgrx::spi::Error> {
+    mtree_set_leaf_preimage(tree, depth, index_in_mtree, preimage)
+}
+
+fn mtree_set_leaf_preimage(tree: &str, depth: i16, index_in_mtree: i64, preimage: VariadicArray<'_, PgFr>) -> Result<PgFr, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    let preimage: Vec<PgFr> = preimage
+        .iter()
+        .map(|value| {
+            value.unwrap_or_else(|| {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_NULL_VALUE_NOT_ALLOWED,
+                    "preimage cannot contain NULL values"
+                );
+            })
+        })
+        .collect();
+
+    let leaf_value = PgFr(preimage_hash(&preimage.iter().map(|v| v.0).collect::<Vec<Fr>>()));
+
+    // Note: set_leaf removes any previous preimage so it must be called first
+    pgfr_mtree_set_leaf(depth, index_in_mtree, leaf_value, tree)?;
+
+    let query = r#"
+        INSERT INTO pgfr_mtree_preimage (tree, leaf_index, preimage)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (tree, leaf_index) DO UPDATE SET preimage = EXCLUDED.preimage
+    "#;
+
+    Spi::run_with_args(query, &[tree.into(), index_in_mtree.into(), preimage.into()])?;
+
+    Ok(leaf_value)
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_get_preimage(index_in_mtree: i64, tree: default!(&str, "'pgfr_mtree'")) -> Result<Option<Vec<PgFr>>, pgrx::spi::Error> {
+    // Note: the subquery always returns 1 row (NULL if the leaf has no preimage)
+    Spi::get_one_with_args(
+        "SELECT (SELECT preimage FROM pgfr_mtree_preimage WHERE tree = $1 AND leaf_index = $2)",
+        &[tree.into(), index_in_mtree.into()]
+    )
+}
+
+/// Recompute the leaves from their preimages and return the leaves that do not match
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_check_preimages(depth: i16, tree: default!(&str, "'pgfr_mtree'")) -> Result<
+    TableIterator<'static, (
+        name!(index_in_mtree, i64),
+        name!(leaf_value, PgFr),
+        name!(expected_value, PgFr),
+    )>,
+    pgrx::spi::Error
+> {
+
+    let table = mtree_table(tree, Some(depth))?;
+    let query = format!(r#"
+        SELECT p.leaf_index, m.value, p.preimage
+        FROM pgfr_mtree_preimage p
+        JOIN {table} m
+            ON m.index_in_mtree = (1::bigint << $1) + p.leaf_index - 1
+        WHERE p.tree = $2
+        ORDER BY p.leaf_index
+    "#);
+
+    let mismatches = Spi::connect(|client| {
+        let mut mismatches = Vec::new();
+        for row in client.select(&query, None, &[i32::from(depth).into(), tree.into()])? {
+            let index_in_mtree = row.get::<i64>(1)?.expect("leaf_index is not null");
+            let leaf_value = row.get::<PgFr>(2)?.expect("value is not null");
+            let preimage = row.get::<Vec<PgFr>>(3)?.expect("preimage is not null");
+            let expected_value = preimage_hash(&preimage.iter().map(|v| v.0).collect::<Vec<Fr>>());
+            if leaf_value.0 != expected_value {
+                mismatches.push((index_in_mtree, leaf_value, PgFr(expected_value)));
+            }
+        }
+        Ok::<_, pgrx::spi::Error>(mismatches)
+    })?;
+
+    Ok(TableIterator::new(mismatches))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+    use merkle_core::poseidon::poseidon_hash_;
+
+    #[pg_test]
+    fn test_pgfr_set_leaf_preimage() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf_preimage(3::smallint, 1, '12'::pgfr, '1'::pgfr);
+            "
+        ).unwrap();
+
+        let leaf = poseidon_hash_(&[Fr::from(12), Fr::from(1)]);
+        let value = Spi::get_one::<PgFr>("SELECT value FROM pgfr_mtree WHERE index_in_mtree = 8;")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value.0, leaf);
+
+        let preimage = pgfr_mtree_get_preimage(1, DEFAULT_TREE).unwrap().unwrap();
+        assert_eq!(preimage.iter().map(|v| v.0).collect::<Vec<Fr>>(), vec![Fr::from(12), Fr::from(1)]);
+        assert_eq!(pgfr_mtree_check_preimages(3, DEFAULT_TREE).unwrap().count(), 0);
+
+        // A raw update of the leaf is detected
+        Spi::run("UPDATE pgfr_mtree SET value = '3' WHERE index_in_mtree = 8;").unwrap();
+        let mismatches: Vec<_> = pgfr_mtree_check_preimages(3, DEFAULT_TREE).unwrap().collect();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].0, 1);
+        assert_eq!(mismatches[0].2.0, leaf);
+
+        // Setting the leaf directly removes the preimage
+        pgfr_mtree_set_leaf(3, 1, PgFr(Fr::from(2)), DEFAULT_TREE).unwrap();
+        assert!(pgfr_mtree_get_preimage(1, DEFAULT_TREE).unwrap().is_none());
+    }
+
+    #[pg_test]
+    fn test_pgfr_set_leaf_preimage_in() {
+        let _res = Spi::run("
+            SELECT pgfr_mtree_create('members_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaf_preimage_in(3::smallint, 1, 'members_tree', '12'::pgfr, '1'::pgfr);
+            "
+        ).unwrap();
+
+        let preimage = pgfr_mtree_get_preimage(1, "members_tree").unwrap().unwrap();
+        assert_eq!(preimage.iter().map(|v| v.0).collect::<Vec<Fr>>(), vec![Fr::from(12), Fr::from(1)]);
+        assert_eq!(pgfr_mtree_check_preimages(3, "members_tree").unwrap().count(), 0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Cannot hash a preimage of 9 elements")]
+    fn test_pgfr_set_leaf_preimage_too_long() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint, value pgfr);
+            CREATE UNIQUE INDEX pgfr_mtree_index ON pgfr_mtree (index_in_mtree);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf_preimage(3::smallint, 1, VARIADIC ARRAY['1', '2', '3', '4', '5', '6', '7', '8', '9']::pgfr[]);
+            "
+        ).unwrap();
+    }
+}
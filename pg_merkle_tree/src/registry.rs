@@ -0,0 +1,327 @@
+// pgrx
+use pgrx::{
+    spi::{quote_identifier, quote_qualified_identifier, SpiResult},
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::changes::{mtree_log_changes, LeafChange};
+use crate::notify::mtree_notify;
+
+/// Table used by the pgfr_mtree_* functions when no tree is given
+pub(crate) const DEFAULT_TREE: &str = "pgfr_mtree";
+
+// Note: the largest depth for pgfr_mtree_create (a tree stores 2^(depth + 1) - 1 rows)
+pub(crate) const MAX_CREATE_DEPTH: i16 = 32;
+
+/// Raise an error if the depth is not between 1 and MAX_CREATE_DEPTH
+pub(crate) fn mtree_check_depth(depth: i32) {
+    if !(1..=i32::from(MAX_CREATE_DEPTH)).contains(&depth) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("merkle tree depth must be between 1 and {MAX_CREATE_DEPTH}, received {depth}")
+        );
+    }
+}
+
+// Trees created by pgfr_mtree_create (a tree created manually, e.g. pgfr_mtree, is not registered)
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_mtree_registry (
+    tree_id serial PRIMARY KEY,
+    name text NOT NULL UNIQUE,
+    depth smallint NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    -- Last modification of the tree (cf. mtree_touch)
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    -- Incremented on each modification of the tree
+    root_seq bigint NOT NULL DEFAULT 0,
+    -- Channel notified on each modification of the tree (cf. pgfr_mtree_set_notify)
+    notify_channel text
+);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_mtree_registry', '');
+"#,
+    name = "create_mtree_registry",
+);
+
+/// Registered depth of a tree (None if the tree was not created with pgfr_mtree_create)
+pub(crate) fn mtree_registered_depth(tree: &str) -> SpiResult<Option<i16>> {
+    Spi::get_one_with_args(
+        "SELECT (SELECT depth FROM pgfr_mtree_registry WHERE name = $1)",
+        &[tree.into()]
+    )
+}
+
+/// Lock a tree until the end of the transaction, to be called before reading the nodes to modify
+///
+/// Note: the modifications of a tree are serialized (a concurrent modification would compute the nodes from
+///       stale values and overwrite the other one). Advisory lock: the tree is not necessarily registered
+pub(crate) fn mtree_lock(tree: &str) -> SpiResult<()> {
+    Spi::run_with_args("SELECT pg_advisory_xact_lock(hashtext('pg_merkle_tree'), hashtext($1))", &[tree.into()])
+}
+
+/// Record a modification of a tree: log the leaf changes and notify the tree channel if any
+/// (no-op if the tree is not registered)
+pub(crate) fn mtree_touch(tree: &str, changes: Vec<LeafChange>) -> SpiResult<()> {
+    let query = r#"
+        UPDATE pgfr_mtree_registry
+        SET updated_at = now(), root_seq = root_seq + 1
+        WHERE name = $1
+        RETURNING tree_id, root_seq, notify_channel
+    "#;
+
+    let touched = Spi::connect_mut(|client| {
+        let mut rows = client.update(query, None, &[tree.into()])?;
+        let Some(row) = rows.next() else {
+            return Ok::<_, pgrx::spi::Error>(None);
+        };
+        Ok(Some((
+            row.get::<i32>(1)?.expect("tree_id is not null"),
+            row.get::<i64>(2)?.expect("root_seq is not null"),
+            row.get::<String>(3)?,
+        )))
+    })?;
+
+    let Some((tree_id, root_seq, channel)) = touched else {
+        return Ok(());
+    };
+
+    let root = crate::merkle_tree::pgfr_mtree_get_root(tree)?.expect("merkle tree is initialized").0;
+    mtree_log_changes(tree_id, root_seq, root, changes)?;
+    match channel {
+        Some(channel) => mtree_notify(&channel, tree_id, tree, root_seq, root),
+        None => Ok(()),
+    }
+}
+
+/// Quoted table name of a tree, a schema qualified name (schema.table) is quoted as such
+fn mtree_quote_table(tree: &str) -> String {
+    match tree.split_once('.') {
+        Some((schema, table)) => quote_qualified_identifier(schema, table),
+        None => quote_identifier(tree),
+    }
+}
+
+/// Quoted table name of a tree, to be used in a query
+///
+/// If the tree is registered, depth (if any) must be the registered depth
+pub(crate) fn mtree_table(tree: &str, depth: Option<i16>) -> SpiResult<String> {
+    if let Some(depth) = depth {
+        if let Some(registered_depth) = mtree_registered_depth(tree)? {
+            if registered_depth != depth {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("merkle tree {tree} has depth {registered_depth}, received depth {depth}")
+                );
+            }
+        }
+    }
+    Ok(mtree_quote_table(tree))
+}
+
+/// Create a table for a merkle tree of the given depth, initialize it and register it. Returns the root
+///
+/// The table is protected (cf. pgfr_mtree_protect). A fillfactor < 100 keeps some free space in each page
+/// so that the node updates can be HOT updates (the index on index_in_mtree is never updated).
+/// Other storage parameters of the table can be given as is (e.g. 'autovacuum_vacuum_scale_factor = 0.01')
+///
+/// Note: the name can be schema qualified (schema.table), it is then used as such by all the pgfr_mtree_* functions
+#[pg_extern(parallel_unsafe)]
+fn pgfr_mtree_create(
+    name: &str,
+    depth: i32,
+    fillfactor: default!(i32, 80),
+    storage_parameters: default!(Option<&str>, "NULL"),
+) -> Result<PgFr, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+    mtree_check_depth(depth);
+    // unwrap safe: depth <= MAX_CREATE_DEPTH
+    let depth = i16::try_from(depth).unwrap();
+
+    let table = mtree_quote_table(name);
+    let storage_parameters = storage_parameters
+        .map(|parameters| format!(", {parameters}"))
+        .unwrap_or_default();
+    Spi::run(&format!(r#"
+        CREATE TABLE {table} (
+            index_in_mtree bigint PRIMARY KEY,
+            value pgfr NOT NULL
+        ) WITH (fillfactor = {fillfactor}{storage_parameters})
+    "#))?;
+
+    Spi::run_with_args(
+        "INSERT INTO pgfr_mtree_registry (name, depth) VALUES ($1, $2)",
+        &[name.into(), depth.into()]
+    )?;
+
+    crate::merkle_tree::mtree_init(name, depth)?;
+
+    Spi::run_with_args("SELECT pgfr_mtree_protect($1::regclass)", &[table.into()])?;
+
+    let root = crate::merkle_tree::pgfr_mtree_get_root(name)?;
+    Ok(root.expect("merkle tree is initialized"))
+}
+
+/// Drop a merkle tree created by pgfr_mtree_create. Returns false if the tree is not registered
+///
+/// The sync triggers of the tables attached to the tree are dropped (cf. pgfr_mtree_detach)
+#[pg_extern(parallel_unsafe)]
+fn pgfr_mtree_drop(name: &str) -> Result<bool, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+
+    let deleted = Spi::get_one_with_args::<bool>(
+        r#"
+        WITH deleted AS (DELETE FROM pgfr_mtree_registry WHERE name = $1 RETURNING 1)
+        SELECT EXISTS (SELECT 1 FROM deleted)
+        "#,
+        &[name.into()]
+    )?.unwrap_or(false);
+
+    if deleted {
+        // Note: the tree is the 4th argument of the sync triggers (tgargs: arguments terminated by \000)
+        Spi::run_with_args(
+            r#"
+            SELECT pgfr_mtree_detach(source::regclass)
+            FROM (
+                SELECT DISTINCT tgrelid AS source
+                FROM pg_trigger
+                WHERE tgname IN ('pgfr_mtree_sync_insert', 'pgfr_mtree_sync_update', 'pgfr_mtree_sync_delete')
+                    AND (string_to_array(encode(tgargs, 'escape'), '\000'))[4] = $1
+            ) AS attached
+            "#,
+            &[name.into()]
+        )?;
+        Spi::run(&format!("DROP TABLE {}", mtree_quote_table(name)))?;
+        Spi::run_with_args("DELETE FROM pgfr_mtree_preimage WHERE tree = $1", &[name.into()])?;
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use ark_bn254::Fr;
+    use super::*;
+
+    #[pg_test]
+    fn test_mtree_create_drop() {
+        let root = pgfr_mtree_create("members_tree", 3, 80, None).unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+        assert_eq!(mtree_registered_depth("members_tree").unwrap(), Some(3));
+
+        let (rows, fillfactor) = Spi::get_two::<i64, Vec<String>>("
+            SELECT (SELECT count(*) FROM members_tree), reloptions::text[]
+            FROM pg_class WHERE relname = 'members_tree'
+            "
+        ).unwrap();
+        assert_eq!(rows, Some(15));
+        assert_eq!(fillfactor, Some(vec!["fillfactor=80".to_string()]));
+
+        // 2 trees side by side: the default tree (pgfr_mtree) and members_tree
+        Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2', 'members_tree');
+            SELECT pgfr_mtree_set_leaf(3::smallint, 7, '42', 'members_tree');
+            "
+        ).unwrap();
+        let root = Spi::get_one::<PgFr>("SELECT pgfr_mtree_get_root('members_tree');").unwrap().unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        let default_root = Spi::get_one::<PgFr>("SELECT pgfr_mtree_get_root();").unwrap().unwrap();
+        assert_eq!(
+            default_root.0,
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+
+        assert!(pgfr_mtree_drop("members_tree").unwrap());
+        assert!(!pgfr_mtree_drop("members_tree").unwrap());
+        assert_eq!(mtree_registered_depth("members_tree").unwrap(), None);
+        let exists = Spi::get_one::<bool>("SELECT to_regclass('members_tree') IS NOT NULL;").unwrap().unwrap();
+        assert!(!exists);
+    }
+
+    #[pg_test]
+    fn test_mtree_create_options() {
+        pgfr_mtree_create("members_tree", 3, 90, Some("autovacuum_vacuum_scale_factor = 0.01")).unwrap();
+        let options = Spi::get_one::<Vec<String>>(
+            "SELECT reloptions::text[] FROM pg_class WHERE relname = 'members_tree';"
+        ).unwrap();
+        assert_eq!(
+            options,
+            Some(vec!["fillfactor=90".to_string(), "autovacuum_vacuum_scale_factor=0.01".to_string()])
+        );
+
+        // Schema qualified name
+        Spi::run("CREATE SCHEMA app;").unwrap();
+        pgfr_mtree_create("app.members_tree", 3, 80, None).unwrap();
+        let (rows, root) = Spi::get_two::<i64, PgFr>("
+            SELECT (SELECT count(*) FROM app.members_tree), pgfr_mtree_set_leaf(3::smallint, 0, '2', 'app.members_tree');
+            "
+        ).unwrap();
+        assert_eq!(rows, Some(15));
+        assert!(root.is_some());
+        assert!(pgfr_mtree_drop("app.members_tree").unwrap());
+        let exists = Spi::get_one::<bool>("SELECT to_regclass('app.members_tree') IS NOT NULL;").unwrap().unwrap();
+        assert!(!exists);
+    }
+
+    #[pg_test]
+    fn test_mtree_lock() {
+        pgfr_mtree_create("members_tree", 3, 80, None).unwrap();
+        Spi::run("SELECT pgfr_mtree_set_leaf(3::smallint, 0, '2', 'members_tree');").unwrap();
+
+        // The tree stays locked until the end of the transaction
+        let locked = Spi::get_one::<bool>("
+            SELECT EXISTS (
+                SELECT 1 FROM pg_locks
+                WHERE locktype = 'advisory' AND pid = pg_backend_pid() AND granted
+                    AND classid::int = hashtext('pg_merkle_tree') AND objid::int = hashtext('members_tree')
+            );
+            "
+        ).unwrap();
+        assert_eq!(locked, Some(true));
+    }
+
+    #[pg_test]
+    fn test_mtree_drop_attached() {
+        pgfr_mtree_create("members_tree", 3, 80, None).unwrap();
+        Spi::run("
+            CREATE TABLE members (leaf_index bigint, commitment pgfr);
+            SELECT pgfr_mtree_attach('members', 'commitment', 'leaf_index', 3::smallint, 'members_tree');
+            "
+        ).unwrap();
+        assert!(pgfr_mtree_drop("members_tree").unwrap());
+
+        // The sync triggers are dropped with the tree
+        let triggers = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_trigger WHERE tgrelid = 'members'::regclass AND tgname LIKE 'pgfr_mtree_sync%';"
+        ).unwrap();
+        assert_eq!(triggers, Some(0));
+        Spi::run("INSERT INTO members VALUES (0, '2');").unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "merkle tree members_tree has depth 3, received depth 4")]
+    fn test_mtree_create_wrong_depth() {
+        pgfr_mtree_create("members_tree", 3, 80, None).unwrap();
+        Spi::run("SELECT pgfr_mtree_set_leaf(4::smallint, 0, '2', 'members_tree');").unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "table members_tree can only be modified by the pg_merkle_tree functions")]
+    fn test_mtree_create_protected() {
+        pgfr_mtree_create("members_tree", 3, 80, None).unwrap();
+        Spi::run("UPDATE members_tree SET value = '1' WHERE index_in_mtree = 7;").unwrap();
+    }
+}
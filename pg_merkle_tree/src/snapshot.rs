@@ -0,0 +1,168 @@
+// third-party
+use ark_bn254::Fr;
+use merkle_core::snapshot::{Snapshot, HASH_POSEIDON_BN254};
+// pgrx
+use pgrx::prelude::*;
+use crate::PgFr;
+use crate::build::mtree_build;
+use crate::merkle_tree::{mtree_leaf_stats, mtree_leaves, pgfr_mtree_get_root};
+use crate::registry::{mtree_check_depth, mtree_lock, mtree_table};
+
+/// Export the merkle tree as a compact snapshot (only the non empty leaves, cf. merkle_core::snapshot)
+///
+/// e.g. COPY (SELECT pgfr_mtree_export(20::smallint)) TO '/tmp/mtree.snapshot' (FORMAT binary)
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_export(depth: i16, tree: default!(&str, "'pgfr_mtree'")) -> Result<Vec<u8>, pgrx::spi::Error> {
+
+    let table = mtree_table(tree, Some(depth))?;
+    let Some(root) = pgfr_mtree_get_root(tree)? else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "Cannot export: merkle tree is not initialized"
+        );
+    };
+
+    let leaves = mtree_leaves(&table, depth)?
+        .into_iter()
+        .map(|(leaf_index, value)| (leaf_index as u64, value))
+        .collect();
+
+    let snapshot = Snapshot {
+        hash_id: HASH_POSEIDON_BN254,
+        depth: depth as u8,
+        empty_leaf: Fr::default(),
+        root: root.0,
+        leaves,
+    };
+
+    Ok(snapshot.to_bytes())
+}
+
+/// Rebuild a merkle tree from a snapshot (the tree must be empty: no rows or only empty leaves, e.g. a tree
+/// created with pgfr_mtree_create). Returns the root
+///
+/// Fails (and nothing is written) if the resulting root is not the root stored in the snapshot
+#[pg_extern(strict, parallel_unsafe)]
+fn pgfr_mtree_import(snapshot: &[u8], tree: default!(&str, "'pgfr_mtree'")) -> Result<PgFr, pgrx::spi::Error> {
+
+    let snapshot = match Snapshot::from_bytes(snapshot) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+                format!("Invalid merkle tree snapshot: {}", e)
+            );
+        }
+    };
+
+    if snapshot.hash_id != HASH_POSEIDON_BN254 || snapshot.empty_leaf != Fr::default() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!(
+                "Unsupported snapshot: hash id {} with empty leaf {}",
+                snapshot.hash_id, snapshot.empty_leaf
+            )
+        );
+    }
+
+    let depth = i16::from(snapshot.depth);
+    mtree_check_depth(depth.into());
+    let table = mtree_table(tree, Some(depth))?;
+    mtree_lock(tree)?;
+    let (leaf_count, _) = mtree_leaf_stats(&table, depth)?;
+    if leaf_count > 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!("Cannot import: table {table} is not empty")
+        );
+    }
+
+    let leaves = snapshot
+        .leaves
+        .iter()
+        .map(|(index, value)| (*index as i64, *value))
+        .collect();
+    let root = mtree_build(tree, depth, leaves)?;
+    if root.0 != snapshot.root {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+            format!("Snapshot root mismatch: expected {} but got {}", snapshot.root, root.0)
+        );
+    }
+
+    Ok(root)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use super::*;
+    use crate::registry::DEFAULT_TREE;
+
+    fn create_mtree() {
+        Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[0, 7], ARRAY['2', '42']::pgfr[]);
+            "
+        ).unwrap();
+    }
+
+    #[pg_test]
+    fn test_mtree_export_import() {
+        create_mtree();
+
+        let snapshot = pgfr_mtree_export(3, DEFAULT_TREE).unwrap();
+        let decoded = Snapshot::from_bytes(&snapshot).unwrap();
+        assert_eq!(decoded.depth, 3);
+        assert_eq!(decoded.leaves, vec![(0, Fr::from(2)), (7, Fr::from(42))]);
+
+        Spi::run("TRUNCATE pgfr_mtree;").unwrap();
+        let root = pgfr_mtree_import(&snapshot, DEFAULT_TREE).unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+        let count = Spi::get_one::<i64>("SELECT count(*) FROM pgfr_mtree;").unwrap().unwrap();
+        assert_eq!(count, 15);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Snapshot root mismatch")]
+    fn test_mtree_import_root_mismatch() {
+        create_mtree();
+
+        let mut snapshot = Snapshot::from_bytes(&pgfr_mtree_export(3, DEFAULT_TREE).unwrap()).unwrap();
+        snapshot.leaves[1].1 = Fr::from(43);
+        Spi::run("TRUNCATE pgfr_mtree;").unwrap();
+        pgfr_mtree_import(&snapshot.to_bytes(), DEFAULT_TREE).unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "table pgfr_mtree is not empty")]
+    fn test_mtree_import_not_empty() {
+        create_mtree();
+        let snapshot = pgfr_mtree_export(3, DEFAULT_TREE).unwrap();
+        pgfr_mtree_import(&snapshot, DEFAULT_TREE).unwrap();
+    }
+
+    #[pg_test]
+    fn test_mtree_import_created_tree() {
+        create_mtree();
+        let snapshot = pgfr_mtree_export(3, DEFAULT_TREE).unwrap();
+
+        Spi::run("SELECT pgfr_mtree_create('members_tree', 3::smallint);").unwrap();
+        let root = pgfr_mtree_import(&snapshot, "members_tree").unwrap();
+        assert_eq!(
+            root.0,
+            Fr::from_str("9164054056146260648413073295070635933539618302378139976693739565479035405901").unwrap()
+        );
+    }
+}
@@ -0,0 +1,239 @@
+// third-party
+use ark_bn254::Fr;
+use ark_ff::PrimeField;
+use merkle_core::{poseidon::poseidon_hash_, proof::MerkleProof};
+use num_bigint::BigUint;
+use once_cell::sync::Lazy;
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::guard::MtreeWriteGuard;
+use crate::sparse_storage::SparseStorage;
+
+// Note: Fr elements are 254 bits long so every key has its own leaf
+const SMT_DEPTH: usize = 254;
+
+static SMT_STORAGE: Lazy<SparseStorage> = Lazy::new(|| SparseStorage::new("pgfr_smt", SMT_DEPTH));
+
+extension_sql!(
+    r#"
+CREATE TABLE pgfr_smt (
+    level smallint NOT NULL,
+    path bytea NOT NULL,
+    value pgfr NOT NULL,
+    PRIMARY KEY (level, path)
+);
+CREATE TABLE pgfr_smt_leaves (
+    key_path bytea PRIMARY KEY,
+    key pgfr NOT NULL,
+    value pgfr NOT NULL
+);
+SELECT pg_catalog.pg_extension_config_dump('pgfr_smt', '');
+SELECT pg_catalog.pg_extension_config_dump('pgfr_smt_leaves', '');
+"#,
+    name = "create_smt_tables",
+    requires = ["create_pgfr_type"]
+);
+
+/// Leaf position in the sparse merkle tree: the key bits (LSB is the leaf level)
+fn smt_position(key: &Fr) -> BigUint {
+    key.into_bigint().into()
+}
+
+/// Leaf value for a key / value pair (an empty leaf is 0)
+fn smt_leaf_hash(key: &Fr, value: &Fr) -> Fr {
+    poseidon_hash_(&[*key, *value])
+}
+
+fn smt_proof(key: &Fr) -> SpiResult<Vec<u8>> {
+    let proof_data = SMT_STORAGE.get_proof(&smt_position(key))?;
+    Ok(MerkleProof { path: proof_data }.to_bytes())
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_smt_get_root() -> Result<PgFr, pgrx::spi::Error> {
+    Ok(PgFr(SMT_STORAGE.get_root()?))
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_smt_get(key: PgFr) -> Result<Option<PgFr>, pgrx::spi::Error> {
+    // Note: the subquery always returns 1 row (NULL if the key is not in the tree)
+    Spi::get_one_with_args(
+        "SELECT (SELECT value FROM pgfr_smt_leaves WHERE key_path = $1)",
+        &[smt_position(&key.0).to_bytes_be().into()]
+    )
+}
+
+/// Lock the leaves until the end of the transaction: the siblings read by SparseStorage::set_leaf
+/// must not change before the path is written (a concurrent insert would be lost in the root)
+fn smt_lock() -> SpiResult<()> {
+    Spi::run("LOCK TABLE pgfr_smt_leaves IN EXCLUSIVE MODE")
+}
+
+/// Insert (or update) a key in the sparse merkle tree. Returns the new root
+#[pg_extern(parallel_unsafe)]
+fn pgfr_smt_insert(key: PgFr, value: PgFr) -> Result<PgFr, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+    smt_lock()?;
+
+    let position = smt_position(&key.0);
+
+    let query = r#"
+        INSERT INTO pgfr_smt_leaves (key_path, key, value)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key_path) DO UPDATE SET value = EXCLUDED.value
+    "#;
+
+    Spi::run_with_args(
+        query,
+        &[position.to_bytes_be().into(), key.into(), value.into()]
+    )?;
+
+    let root = SMT_STORAGE.set_leaf(&position, smt_leaf_hash(&key.0, &value.0))?;
+    Ok(PgFr(root))
+}
+
+/// Remove a key from the sparse merkle tree. Returns false if the key was not in the tree
+#[pg_extern(parallel_unsafe)]
+fn pgfr_smt_delete(key: PgFr) -> Result<bool, pgrx::spi::Error> {
+    let _guard = MtreeWriteGuard::new();
+    smt_lock()?;
+
+    let position = smt_position(&key.0);
+
+    let deleted = Spi::get_one_with_args::<bool>(
+        r#"
+        WITH deleted AS (DELETE FROM pgfr_smt_leaves WHERE key_path = $1 RETURNING 1)
+        SELECT EXISTS (SELECT 1 FROM deleted)
+        "#,
+        &[position.to_bytes_be().into()]
+    )?.unwrap_or(false);
+
+    if deleted {
+        SMT_STORAGE.set_leaf(&position, Fr::default())?;
+    }
+
+    Ok(deleted)
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_smt_membership_proof(key: PgFr) -> Result<Vec<u8>, pgrx::spi::Error> {
+    if pgfr_smt_get(key)?.is_none() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NO_DATA_FOUND,
+            format!("key {} is not in the sparse merkle tree", key.0)
+        );
+    }
+    smt_proof(&key.0)
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_smt_non_membership_proof(key: PgFr) -> Result<Vec<u8>, pgrx::spi::Error> {
+    if pgfr_smt_get(key)?.is_some() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNIQUE_VIOLATION,
+            format!("key {} is in the sparse merkle tree", key.0)
+        );
+    }
+    smt_proof(&key.0)
+}
+
+/// Verify a membership proof (value is not NULL) or a non-membership proof (value is NULL)
+#[pg_extern(immutable, parallel_safe)]
+fn pgfr_smt_verify_proof(key: PgFr, value: Option<PgFr>, proof: &[u8], root: PgFr) -> bool {
+
+    let proof = match MerkleProof::from_bytes(proof) {
+        Ok(proof) => proof,
+        Err(e) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+                format!("Invalid encoding for sparse merkle tree proof: {}", e)
+            );
+        }
+    };
+
+    // The proof path must be the one given by the key bits
+    let position = smt_position(&key.0);
+    let valid_path = proof.path.len() == SMT_DEPTH
+        && proof.path
+            .iter()
+            .enumerate()
+            .all(|(bit, (left_or_right, _))| *left_or_right == i64::from(position.bit(bit as u64)));
+
+    let leaf = match value {
+        Some(value) => smt_leaf_hash(&key.0, &value.0),
+        None => Fr::default(),
+    };
+
+    valid_path && proof.verify(leaf, root.0)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use super::*;
+    use merkle_core::default_hashes;
+
+    #[pg_test]
+    fn test_smt_insert_get_delete() {
+
+        let empty_root = pgfr_smt_get_root().unwrap();
+        assert_eq!(empty_root.0, default_hashes(SMT_DEPTH)[SMT_DEPTH]);
+
+        // Keys 2 & 3 only differ by their last bit (same parent node)
+        let root_1 = pgfr_smt_insert(PgFr(Fr::from(2)), PgFr(Fr::from(42))).unwrap();
+        let root_2 = pgfr_smt_insert(PgFr(Fr::from(3)), PgFr(Fr::from(43))).unwrap();
+        assert_ne!(root_1.0, empty_root.0);
+        assert_ne!(root_2.0, root_1.0);
+        assert_eq!(pgfr_smt_get_root().unwrap().0, root_2.0);
+
+        assert_eq!(pgfr_smt_get(PgFr(Fr::from(2))).unwrap().unwrap().0, Fr::from(42));
+        assert_eq!(pgfr_smt_get(PgFr(Fr::from(3))).unwrap().unwrap().0, Fr::from(43));
+        assert!(pgfr_smt_get(PgFr(Fr::from(4))).unwrap().is_none());
+
+        // Delete keys in the insertion reverse order: roots must match
+        assert!(pgfr_smt_delete(PgFr(Fr::from(3))).unwrap());
+        assert!(!pgfr_smt_delete(PgFr(Fr::from(3))).unwrap());
+        assert_eq!(pgfr_smt_get_root().unwrap().0, root_1.0);
+        assert!(pgfr_smt_delete(PgFr(Fr::from(2))).unwrap());
+        assert_eq!(pgfr_smt_get_root().unwrap().0, empty_root.0);
+
+        // Only non default nodes are stored
+        let count = Spi::get_one::<i64>("SELECT count(*) FROM pgfr_smt;").unwrap().unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[pg_test]
+    fn test_smt_proofs() {
+
+        let key = PgFr(Fr::from(2));
+        let other_key = PgFr(Fr::from(-1));
+        pgfr_smt_insert(key, PgFr(Fr::from(42))).unwrap();
+        let root = pgfr_smt_insert(PgFr(Fr::from(7)), PgFr(Fr::from(1))).unwrap();
+
+        let proof = pgfr_smt_membership_proof(key).unwrap();
+        assert!(pgfr_smt_verify_proof(key, Some(PgFr(Fr::from(42))), &proof, root));
+        assert!(!pgfr_smt_verify_proof(key, Some(PgFr(Fr::from(43))), &proof, root));
+        assert!(!pgfr_smt_verify_proof(key, None, &proof, root));
+
+        let proof = pgfr_smt_non_membership_proof(other_key).unwrap();
+        assert!(pgfr_smt_verify_proof(other_key, None, &proof, root));
+        // A non-membership proof is only valid for the key it was generated for
+        assert!(!pgfr_smt_verify_proof(PgFr(Fr::from(3)), None, &proof, root));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "is in the sparse merkle tree")]
+    fn test_smt_non_membership_proof_fail() {
+        let key = PgFr(Fr::from(2));
+        pgfr_smt_insert(key, PgFr(Fr::from(42))).unwrap();
+        pgfr_smt_non_membership_proof(key).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// std
+use std::collections::HashMap;
+// third-party
+use ark_bn254::Fr;
+use num_bigint::BigUint;
+// pgrx
+use pgrx::{
+    spi::SpiResult,
+    prelude::*,
+};
+use merkle_core::{default_hashes, poseidon::poseidon_hash_};
+use crate::PgFr;
+
+/// Merkle tree storage where only the nodes that differ from an empty node are stored
+///
+/// A node is identified by its level (0: root, depth: leaves) and its path
+/// (the leaf position shifted right by depth - level) stored as big endian bytes.
+/// Table structure: (level smallint, path bytea, value pgfr, PRIMARY KEY (level, path))
+pub(crate) struct SparseStorage {
+    table: &'static str,
+    depth: usize,
+    // Hash of an empty node, from the leaves (index 0) up to the root (index depth)
+    default_hashes: Vec<Fr>,
+}
+
+impl SparseStorage {
+
+    pub(crate) fn new(table: &'static str, depth: usize) -> Self {
+        Self {
+            table,
+            depth,
+            default_hashes: default_hashes(depth),
+        }
+    }
+
+    fn default_hash(&self, level: usize) -> Fr {
+        self.default_hashes[self.depth - level]
+    }
+
+    fn node_path(&self, position: &BigUint, level: usize) -> BigUint {
+        position >> (self.depth - level)
+    }
+
+    /// Get the node values, falling back to the empty node value if a node is not stored
+    fn get_nodes(&self, nodes: &[(usize, BigUint)]) -> SpiResult<Vec<Fr>> {
+
+        let query = format!(r#"
+            SELECT n.level, n.path, n.value
+            FROM UNNEST($1::smallint[], $2::bytea[]) AS t(level, path)
+            JOIN {} n
+                ON n.level = t.level AND n.path = t.path
+        "#, self.table);
+
+        let (levels, paths): (Vec<i16>, Vec<Vec<u8>>) = nodes
+            .iter()
+            .map(|(level, path)| (*level as i16, path.to_bytes_be()))
+            .unzip();
+
+        let stored = Spi::connect(|client| {
+            let mut stored = HashMap::new();
+            for row in client.select(&query, None, &[levels.into(), paths.into()])? {
+                let level = row.get::<i16>(1)?.expect("level is not null");
+                let path = row.get::<Vec<u8>>(2)?.expect("path is not null");
+                let value = row.get::<PgFr>(3)?.expect("value is not null");
+                stored.insert((level as usize, path), value.0);
+            }
+            Ok::<_, pgrx::spi::Error>(stored)
+        })?;
+
+        let values = nodes
+            .iter()
+            .map(|(level, path)| {
+                stored
+                    .get(&(*level, path.to_bytes_be()))
+                    .copied()
+                    .unwrap_or_else(|| self.default_hash(*level))
+            })
+            .collect();
+
+        Ok(values)
+    }
+
+    pub(crate) fn get_root(&self) -> SpiResult<Fr> {
+        let root = self.get_nodes(&[(0, BigUint::ZERO)])?;
+        Ok(root[0])
+    }
+
+    /// Sibling nodes of a leaf, from the leaf level up to the level below the root
+    fn get_siblings(&self, position: &BigUint) -> SpiResult<Vec<Fr>> {
+        let one = BigUint::from(1u8);
+        let siblings: Vec<(usize, BigUint)> = (1..=self.depth)
+            .rev()
+            .map(|level| (level, self.node_path(position, level) ^ &one))
+            .collect();
+        self.get_nodes(&siblings)
+    }
+
+    /// Proof with the same layout as pgfr_mtree_get_proof: (0 if left child or 1 if right child, sibling value)
+    pub(crate) fn get_proof(&self, position: &BigUint) -> SpiResult<Vec<(i64, Fr)>> {
+        let siblings = self.get_siblings(position)?;
+        let proof_data = (0..self.depth)
+            .map(|bit| i64::from(position.bit(bit as u64)))
+            .zip(siblings)
+            .collect();
+        Ok(proof_data)
+    }
+
+    /// Set a leaf value then update all the nodes up to the root. Returns the new root
+    pub(crate) fn set_leaf(&self, position: &BigUint, leaf_value: Fr) -> SpiResult<Fr> {
+
+        let siblings = self.get_siblings(position)?;
+
+        // Compute the new nodes from the leaf up to the root
+        let mut nodes = Vec::with_capacity(self.depth + 1);
+        nodes.push((self.depth, leaf_value));
+        let mut node_value = leaf_value;
+        for (bit, sibling) in siblings.into_iter().enumerate() {
+            node_value = match position.bit(bit as u64) {
+                false => poseidon_hash_(&[node_value, sibling]),
+                true => poseidon_hash_(&[sibling, node_value]),
+            };
+            nodes.push((self.depth - bit - 1, node_value));
+        }
+
+        // Empty nodes are removed from the table so it only stores the non default nodes
+        let mut to_upsert = (Vec::new(), Vec::new(), Vec::new());
+        let mut to_delete = (Vec::new(), Vec::new());
+        for (level, value) in nodes {
+            let path = self.node_path(position, level).to_bytes_be();
+            if value == self.default_hash(level) {
+                to_delete.0.push(level as i16);
+                to_delete.1.push(path);
+            } else {
+                to_upsert.0.push(level as i16);
+                to_upsert.1.push(path);
+                to_upsert.2.push(PgFr(value));
+            }
+        }
+
+        let query_upsert = format!(r#"
+            INSERT INTO {} (level, path, value)
+            SELECT * FROM UNNEST($1::smallint[], $2::bytea[], $3::pgfr[])
+            ON CONFLICT (level, path) DO UPDATE SET value = EXCLUDED.value
+        "#, self.table);
+
+        let query_delete = format!(r#"
+            DELETE FROM {} n
+            USING UNNEST($1::smallint[], $2::bytea[]) AS t(level, path)
+            WHERE n.level = t.level AND n.path = t.path
+        "#, self.table);
+
+        Spi::run_with_args(
+            &query_upsert,
+            &[to_upsert.0.into(), to_upsert.1.into(), to_upsert.2.into()]
+        )?;
+        Spi::run_with_args(
+            &query_delete,
+            &[to_delete.0.into(), to_delete.1.into()]
+        )?;
+
+        Ok(node_value)
+    }
+}
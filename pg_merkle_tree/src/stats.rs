@@ -0,0 +1,166 @@
+// pgrx
+use pgrx::{
+    datum::TimestampWithTimeZone,
+    prelude::*,
+};
+use crate::PgFr;
+use crate::merkle_tree::{mtree_leaf_stats, pgfr_mtree_get_root};
+use crate::registry::{mtree_registered_depth, mtree_table};
+
+/// Statistics of a merkle tree
+///
+/// Note: for a tree that is not registered (e.g. pgfr_mtree), the depth is computed from the number of levels
+///       of the table and updated_at is NULL
+#[pg_extern(stable, strict, parallel_safe)]
+fn pgfr_mtree_stats(tree: default!(&str, "'pgfr_mtree'")) -> Result<
+    TableIterator<'static, (
+        name!(tree, String),
+        name!(depth, i16),
+        name!(capacity, i64),
+        name!(leaves, i64),
+        name!(next_index, i64),
+        name!(storage_rows, i64),
+        name!(size_bytes, i64),
+        name!(root, Option<PgFr>),
+        name!(updated_at, Option<TimestampWithTimeZone>),
+    )>,
+    pgrx::spi::Error
+> {
+    let table = mtree_table(tree, None)?;
+
+    let (storage_rows, max_index) = Spi::get_two::<i64, i64>(
+        &format!("SELECT count(*), max(index_in_mtree) FROM {table}")
+    )?;
+    let storage_rows = storage_rows.unwrap_or(0);
+    let depth = match (mtree_registered_depth(tree)?, max_index) {
+        (Some(depth), _) => depth,
+        // Note: the last level ends at index 2^(depth + 1) - 2
+        (None, Some(max_index)) if max_index >= 0 => (63 - (max_index + 1).leading_zeros()) as i16,
+        (None, _) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+                format!("merkle tree {tree} is not initialized")
+            );
+        }
+    };
+
+    let (leaves, next_index) = mtree_leaf_stats(&table, depth)?;
+
+    let size_bytes = Spi::get_one_with_args::<i64>(
+        "SELECT pg_total_relation_size($1::regclass)",
+        &[table.as_str().into()]
+    )?;
+    let updated_at = Spi::get_one_with_args::<TimestampWithTimeZone>(
+        "SELECT (SELECT updated_at FROM pgfr_mtree_registry WHERE name = $1)",
+        &[tree.into()]
+    )?;
+
+    Ok(TableIterator::once((
+        tree.to_string(),
+        depth,
+        1i64 << depth,
+        leaves,
+        next_index,
+        storage_rows,
+        size_bytes.unwrap_or(0),
+        pgfr_mtree_get_root(tree)?,
+        updated_at,
+    )))
+}
+
+/// All the merkle trees created by pgfr_mtree_create
+#[pg_extern(stable, parallel_safe)]
+fn pgfr_mtree_list() -> Result<
+    TableIterator<'static, (
+        name!(tree_id, i32),
+        name!(tree, String),
+        name!(depth, i16),
+        name!(root, Option<PgFr>),
+        name!(created_at, TimestampWithTimeZone),
+        name!(updated_at, TimestampWithTimeZone),
+    )>,
+    pgrx::spi::Error
+> {
+    let query = r#"
+        SELECT tree_id, name, depth, created_at, updated_at
+        FROM pgfr_mtree_registry
+        ORDER BY tree_id
+    "#;
+
+    let rows = Spi::connect(|client| {
+        let mut rows = Vec::new();
+        for row in client.select(query, None, &[])? {
+            rows.push((
+                row.get::<i32>(1)?.expect("tree_id is not null"),
+                row.get::<String>(2)?.expect("name is not null"),
+                row.get::<i16>(3)?.expect("depth is not null"),
+                row.get::<TimestampWithTimeZone>(4)?.expect("created_at is not null"),
+                row.get::<TimestampWithTimeZone>(5)?.expect("updated_at is not null"),
+            ));
+        }
+        Ok::<_, pgrx::spi::Error>(rows)
+    })?;
+
+    let trees = rows
+        .into_iter()
+        .map(|(tree_id, tree, depth, created_at, updated_at)| {
+            let root = pgfr_mtree_get_root(&tree)?;
+            Ok((tree_id, tree, depth, root, created_at, updated_at))
+        })
+        .collect::<Result<Vec<_>, pgrx::spi::Error>>()?;
+
+    Ok(TableIterator::new(trees))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+
+    use std::str::FromStr;
+    use ark_bn254::Fr;
+    use super::*;
+    use crate::registry::DEFAULT_TREE;
+
+    #[pg_test]
+    fn test_mtree_stats() {
+        let _res = Spi::run("
+            SELECT pgfr_mtree_create('members_tree', 3::smallint);
+            SELECT pgfr_mtree_set_leaves(3::smallint, ARRAY[0, 5], ARRAY['2', '42']::pgfr[], 'members_tree');
+            "
+        ).unwrap();
+
+        let (tree, depth, capacity, leaves, next_index, storage_rows, size_bytes, root, updated_at) =
+            pgfr_mtree_stats("members_tree").unwrap().next().unwrap();
+        assert_eq!(tree, "members_tree");
+        assert_eq!((depth, capacity, leaves, next_index, storage_rows), (3, 8, 2, 6, 15));
+        assert!(size_bytes > 0);
+        assert!(root.is_some());
+        assert!(updated_at.is_some());
+
+        let trees: Vec<(String, i16)> = pgfr_mtree_list()
+            .unwrap()
+            .map(|(_, tree, depth, _, _, _)| (tree, depth))
+            .collect();
+        assert_eq!(trees, vec![("members_tree".to_string(), 3)]);
+    }
+
+    #[pg_test]
+    fn test_mtree_stats_unregistered() {
+        let _res = Spi::run("
+            CREATE TABLE pgfr_mtree (index_in_mtree bigint PRIMARY KEY, value pgfr);
+            SELECT pgfr_mtree_init(3);
+            "
+        ).unwrap();
+
+        let (_, depth, _, leaves, next_index, storage_rows, _, root, updated_at) =
+            pgfr_mtree_stats(DEFAULT_TREE).unwrap().next().unwrap();
+        assert_eq!((depth, leaves, next_index, storage_rows), (3, 0, 0, 15));
+        assert_eq!(
+            root.unwrap().0,
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+        assert!(updated_at.is_none());
+        assert_eq!(pgfr_mtree_list().unwrap().count(), 0);
+    }
+}
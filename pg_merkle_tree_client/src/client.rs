@@ -0,0 +1,232 @@
+// third-party
+use ark_bn254::Fr;
+// sqlx
+use sqlx::{postgres::types::Oid, PgPool};
+use crate::types::{PgFrStruct, Proof};
+
+/// Typed access to a merkle tree stored by the pg_merkle_tree extension
+#[derive(Debug, Clone)]
+pub struct MerkleTreeClient {
+    pool: PgPool,
+    depth: i16,
+    tree: String,
+}
+
+/// A leaf change of a tree created with pgfr_mtree_create (cf. pgfr_mtree_changes_since)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafChange {
+    pub seq: i64,
+    pub root_seq: i64,
+    pub leaf_index: i64,
+    pub old_value: Fr,
+    pub new_value: Fr,
+    /// Root of the tree after the modification
+    pub root: Fr,
+}
+
+/// Table used by the pg_merkle_tree functions when no tree is given
+pub const DEFAULT_TREE: &str = "pgfr_mtree";
+
+/// Quote a table name (as quote_ident in PostgreSQL)
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl MerkleTreeClient {
+
+    /// Create a client for a merkle tree of the given depth
+    ///
+    /// Fails if the pg_merkle_tree extension is not installed in the database
+    pub async fn new(pool: PgPool, depth: i16) -> Result<Self, sqlx::Error> {
+        // Check that the pgfr type (and its array type) exists
+        let _row: (Oid, Oid) = sqlx::query_as("SELECT oid, typarray FROM pg_type WHERE typname = 'pgfr'")
+            .fetch_one(&pool)
+            .await?;
+        Ok(MerkleTreeClient { pool, depth, tree: DEFAULT_TREE.to_string() })
+    }
+
+    pub async fn connect(url: &str, depth: i16) -> Result<Self, sqlx::Error> {
+        let pool = PgPool::connect(url).await?;
+        Self::new(pool, depth).await
+    }
+
+    /// Use the given tree (e.g. created with pgfr_mtree_create) instead of pgfr_mtree
+    pub fn with_tree(mut self, tree: &str) -> Self {
+        self.tree = tree.to_string();
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn depth(&self) -> i16 {
+        self.depth
+    }
+
+    pub fn tree(&self) -> &str {
+        &self.tree
+    }
+
+    /// Create (with pgfr_mtree_create) and initialize the tree. Returns the root
+    pub async fn create(&self) -> Result<Fr, sqlx::Error> {
+        let row: (PgFrStruct,) = sqlx::query_as("SELECT pgfr_mtree_create($1, $2)")
+            .bind(&self.tree)
+            .bind(i32::from(self.depth))
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0.inner)
+    }
+
+    /// Initialize the merkle tree (the table of the tree must exist and be empty)
+    pub async fn init(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pgfr_mtree_init($1, $2)")
+            .bind(i64::from(self.depth))
+            .bind(&self.tree)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_leaf(&self, index: i64, value: Fr) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pgfr_mtree_set_leaf($1, $2, $3, $4)")
+            .bind(self.depth)
+            .bind(index)
+            .bind(PgFrStruct::from(value))
+            .bind(&self.tree)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_leaves(&self, indices: &[i64], values: &[Fr]) -> Result<(), sqlx::Error> {
+        let values: Vec<PgFrStruct> = values.iter().copied().map(PgFrStruct::from).collect();
+        sqlx::query("SELECT pgfr_mtree_set_leaves($1, $2, $3, $4)")
+            .bind(self.depth)
+            .bind(indices)
+            .bind(values)
+            .bind(&self.tree)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn leaf(&self, index: i64) -> Result<Fr, sqlx::Error> {
+        let query = format!(
+            "SELECT value FROM {} WHERE index_in_mtree = (1::bigint << $1) + $2 - 1",
+            quote_identifier(&self.tree)
+        );
+        let row: (PgFrStruct,) = sqlx::query_as(&query)
+            .bind(i32::from(self.depth))
+            .bind(index)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0.inner)
+    }
+
+    /// Fails if the tree is not initialized (pgfr_mtree_get_root returns NULL)
+    pub async fn root(&self) -> Result<Fr, sqlx::Error> {
+        let row: (Option<PgFrStruct>,) = sqlx::query_as("SELECT pgfr_mtree_get_root($1)")
+            .bind(&self.tree)
+            .fetch_one(&self.pool)
+            .await?;
+        match row.0 {
+            Some(root) => Ok(root.inner),
+            None => Err(sqlx::Error::Decode(
+                format!("merkle tree {} is not initialized (cf. pgfr_mtree_init)", self.tree).into()
+            )),
+        }
+    }
+
+    pub async fn proof(&self, index: i64) -> Result<Proof, sqlx::Error> {
+        let row: (Proof,) = sqlx::query_as("SELECT pgfr_mtree_get_proof($1, $2, $3)")
+            .bind(self.depth)
+            .bind(index)
+            .bind(&self.tree)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0)
+    }
+
+    /// Root of the tree as of a previous root sequence (cf. LeafChange::root_seq)
+    pub async fn root_at(&self, root_seq: i64) -> Result<Fr, sqlx::Error> {
+        let row: (PgFrStruct,) = sqlx::query_as("SELECT pgfr_mtree_get_root_at($1, $2)")
+            .bind(root_seq)
+            .bind(&self.tree)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0.inner)
+    }
+
+    /// Proof of a leaf against the root of the tree as of a previous root sequence
+    pub async fn proof_at(&self, index: i64, root_seq: i64) -> Result<Proof, sqlx::Error> {
+        let row: (Proof,) = sqlx::query_as("SELECT pgfr_mtree_get_proof_at($1, $2, $3)")
+            .bind(index)
+            .bind(root_seq)
+            .bind(&self.tree)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0)
+    }
+
+    /// All the leaves that are not empty (value != 0), sorted by index
+    pub async fn leaves(&self) -> Result<Vec<(i64, Fr)>, sqlx::Error> {
+        // Note: pgfr has no comparison operator, an empty leaf is 32 zero bytes
+        let query = format!(r#"
+            SELECT index_in_mtree - ((1::bigint << $1) - 1), value
+            FROM {}
+            WHERE index_in_mtree >= (1::bigint << $1) - 1
+                AND pgfr_to_bytea(value) <> decode(repeat('00', 32), 'hex')
+            ORDER BY index_in_mtree
+        "#, quote_identifier(&self.tree));
+        let rows: Vec<(i64, PgFrStruct)> = sqlx::query_as(&query)
+            .bind(i32::from(self.depth))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|(index, value)| (index, value.inner)).collect())
+    }
+
+    /// Leaf changes of the tree after root_seq (0 for all the changes), in order
+    ///
+    /// Note: the cursor for the next call is the root_seq of the last change (not its seq)
+    pub async fn changes_since(&self, root_seq: i64) -> Result<Vec<LeafChange>, sqlx::Error> {
+        let query = r#"
+            SELECT seq, root_seq, leaf_index, old_value, new_value, root
+            FROM pgfr_mtree_changes_since($1, $2)
+        "#;
+        let rows: Vec<(i64, i64, i64, PgFrStruct, PgFrStruct, PgFrStruct)> = sqlx::query_as(query)
+            .bind(root_seq)
+            .bind(&self.tree)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(seq, root_seq, leaf_index, old_value, new_value, root)| LeafChange {
+                seq,
+                root_seq,
+                leaf_index,
+                old_value: old_value.inner,
+                new_value: new_value.inner,
+                root: root.inner,
+            })
+            .collect())
+    }
+
+    /// Check (in the database) that leaf is the value at index for a tree with the given root
+    pub async fn verify(&self, index: i64, leaf: Fr, root: Fr) -> Result<bool, sqlx::Error> {
+        let query = r#"
+            SELECT pgfr_mtree_verify_multiproof(
+                $1, ARRAY[$2], ARRAY[$3], pgfr_mtree_get_multiproof($1, ARRAY[$2], $5), $4
+            )
+        "#;
+        let row: (bool,) = sqlx::query_as(query)
+            .bind(self.depth)
+            .bind(index)
+            .bind(PgFrStruct::from(leaf))
+            .bind(PgFrStruct::from(root))
+            .bind(&self.tree)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0)
+    }
+}
@@ -0,0 +1,5 @@
+mod client;
+mod types;
+
+pub use client::{quote_identifier, LeafChange, MerkleTreeClient, DEFAULT_TREE};
+pub use types::{PgFrStruct, Proof};
@@ -0,0 +1,138 @@
+// third-party
+use ark_serialize::{CanonicalSerialize, CanonicalDeserialize};
+use ark_bn254::Fr;
+// sqlx
+use sqlx::{postgres::{
+    PgHasArrayType,
+    PgTypeInfo,
+    PgArgumentBuffer,
+    PgValueRef,
+    Postgres
+}, Type, Encode, Decode};
+// Note: same parameters as the pg_merkle_tree extension
+use merkle_core::proof::proof_root;
+
+// Note:
+// pgfr oids depend on the database (the type is created by the extension) so the types are declared by name.
+// sqlx resolves (and caches) the oids for each connection of the pool.
+// Postgres automatically creates an array type named with an underscore usually.
+const PGFR_TYPE_NAME: &str = "pgfr";
+const PGFR_ARRAY_TYPE_NAME: &str = "_pgfr";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PgFrStruct {
+    pub inner: Fr,
+}
+
+impl From<Fr> for PgFrStruct {
+    fn from(inner: Fr) -> Self {
+        PgFrStruct { inner }
+    }
+}
+
+impl Type<Postgres> for PgFrStruct {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name(PGFR_TYPE_NAME)
+    }
+}
+
+impl<'q> Encode<'q, Postgres> for PgFrStruct {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<sqlx::encode::IsNull, Box<dyn std::error::Error + Send + Sync>> {
+        let mut temp_buf = Vec::with_capacity(32);
+        self.inner.serialize_compressed(&mut temp_buf)?;
+        buf.extend_from_slice(&temp_buf);
+        Ok(sqlx::encode::IsNull::No)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for PgFrStruct {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = value.as_bytes()?;
+        let fr = Fr::deserialize_compressed(bytes)?;
+        Ok(PgFrStruct { inner: fr })
+    }
+}
+
+impl PgHasArrayType for PgFrStruct {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name(PGFR_ARRAY_TYPE_NAME)
+    }
+}
+
+/// A merkle proof as returned by pgfr_mtree_get_proof: (0 if left child or 1 if right child, sibling value)
+/// from the leaf up to the root
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Proof {
+    pub inner: Vec<(i64, Fr)>
+}
+
+impl Proof {
+    /// Compute the root of the merkle tree from a leaf value and this proof
+    pub fn compute_root(&self, leaf: Fr) -> Fr {
+        proof_root(leaf, &self.inner)
+    }
+
+    /// Check (locally) that leaf is in a merkle tree with the given root
+    pub fn verify(&self, leaf: Fr, root: Fr) -> bool {
+        self.compute_root(leaf) == root
+    }
+}
+
+impl Type<Postgres> for Proof {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("bytea")
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Proof {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = <&[u8] as Decode<Postgres>>::decode(value)?;
+        let inner = Vec::<(i64, Fr)>::deserialize_compressed(bytes)?;
+        Ok(Proof { inner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    // Proof of leaf 0 for a merkle tree of depth 3 (cf. pg_merkle_tree tests)
+    fn proof_leaf_0() -> Proof {
+        Proof {
+            inner: vec![
+                (0, Fr::from(0)),
+                (0, Fr::from_str("14744269619966411208579211824598458697587494354926760081771325075741142829156").unwrap()),
+                (0, Fr::from_str("7423237065226347324353380772367382631490014989348495481811164164159255474657").unwrap())
+            ]
+        }
+    }
+
+    #[test]
+    fn test_proof_compute_root() {
+        let proof = proof_leaf_0();
+        // Empty tree
+        assert_eq!(
+            proof.compute_root(Fr::from(0)),
+            Fr::from_str("11286972368698509976183087595462810875513684078608517520839298933882497716792").unwrap()
+        );
+        // Leaf 0 set to 2
+        assert_eq!(
+            proof.compute_root(Fr::from(2)),
+            Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_proof_verify() {
+        let proof = proof_leaf_0();
+        let root = Fr::from_str("3799385896495180565562780950112041501871782716691607926126180421168246094289").unwrap();
+        assert!(proof.verify(Fr::from(2), root));
+        assert!(!proof.verify(Fr::from(3), root));
+
+        // Same siblings but leaf 1 (right child) gives another root
+        let mut proof_leaf_1 = proof.clone();
+        proof_leaf_1.inner[0].0 = 1;
+        assert!(!proof_leaf_1.verify(Fr::from(2), root));
+    }
+}
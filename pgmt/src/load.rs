@@ -0,0 +1,136 @@
+// std
+use std::str::FromStr;
+// third-party
+use ark_bn254::Fr;
+use serde::Deserialize;
+
+/// Leaf file format for bulk-load / export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LeafFormat {
+    /// One `index,value` per line (an optional `index,value` header is skipped)
+    Csv,
+    /// One `{"index": 1, "value": "42"}` per line
+    Jsonl,
+}
+
+impl LeafFormat {
+    /// Guess the format from the file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()) {
+            Some(ext) if ext == "csv" => Some(LeafFormat::Csv),
+            Some(ext) if ext == "jsonl" || ext == "ndjson" => Some(LeafFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonLeaf {
+    index: i64,
+    // Note: Fr values do not fit in a json number
+    value: String,
+}
+
+pub fn parse_fr(value: &str) -> Result<Fr, String> {
+    Fr::from_str(value.trim()).map_err(|_| format!("Invalid field element: '{}'", value.trim()))
+}
+
+/// Parse a leaf file content to (indices, values)
+pub fn parse_leaves(content: &str, format: LeafFormat) -> Result<(Vec<i64>, Vec<Fr>), String> {
+
+    let mut indices = Vec::new();
+    let mut values = Vec::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (index, value) = match format {
+            LeafFormat::Csv => {
+                if line_number == 0 && line.eq_ignore_ascii_case("index,value") {
+                    continue;
+                }
+                let (index, value) = line
+                    .split_once(',')
+                    .ok_or_else(|| format!("line {}: expected 'index,value'", line_number + 1))?;
+                let index = index
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|e| format!("line {}: invalid index: {}", line_number + 1, e))?;
+                (index, parse_fr(value).map_err(|e| format!("line {}: {}", line_number + 1, e))?)
+            }
+            LeafFormat::Jsonl => {
+                let leaf: JsonLeaf = serde_json::from_str(line)
+                    .map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+                (leaf.index, parse_fr(&leaf.value).map_err(|e| format!("line {}: {}", line_number + 1, e))?)
+            }
+        };
+
+        indices.push(index);
+        values.push(value);
+    }
+
+    Ok((indices, values))
+}
+
+/// Format leaves so that they can be read back by parse_leaves
+pub fn format_leaves(leaves: &[(i64, Fr)], format: LeafFormat) -> String {
+    let mut output = String::new();
+    if format == LeafFormat::Csv {
+        output.push_str("index,value\n");
+    }
+    for (index, value) in leaves {
+        match format {
+            LeafFormat::Csv => output.push_str(&format!("{},{}\n", index, value)),
+            LeafFormat::Jsonl => output.push_str(
+                &format!("{}\n", serde_json::json!({"index": index, "value": value.to_string()}))
+            ),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(LeafFormat::from_path("members.csv"), Some(LeafFormat::Csv));
+        assert_eq!(LeafFormat::from_path("members.JSONL"), Some(LeafFormat::Jsonl));
+        assert_eq!(LeafFormat::from_path("members"), None);
+    }
+
+    #[test]
+    fn test_parse_leaves_csv() {
+        let content = "index,value\n0,2\n\n# comment\n7, 42\n";
+        let (indices, values) = parse_leaves(content, LeafFormat::Csv).unwrap();
+        assert_eq!(indices, vec![0, 7]);
+        assert_eq!(values, vec![Fr::from(2), Fr::from(42)]);
+
+        assert!(parse_leaves("0;2", LeafFormat::Csv).is_err());
+        assert!(parse_leaves("a,2", LeafFormat::Csv).is_err());
+        assert!(parse_leaves("0,abc", LeafFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn test_parse_leaves_jsonl() {
+        let content = "{\"index\": 0, \"value\": \"2\"}\n{\"index\": 7, \"value\": \"42\"}\n";
+        let (indices, values) = parse_leaves(content, LeafFormat::Jsonl).unwrap();
+        assert_eq!(indices, vec![0, 7]);
+        assert_eq!(values, vec![Fr::from(2), Fr::from(42)]);
+
+        assert!(parse_leaves("{\"index\": 0, \"value\": 2}", LeafFormat::Jsonl).is_err());
+    }
+
+    #[test]
+    fn test_format_parse_roundtrip() {
+        let leaves = vec![(0, Fr::from(2)), (7, Fr::from(-1))];
+        for format in [LeafFormat::Csv, LeafFormat::Jsonl] {
+            let (indices, values) = parse_leaves(&format_leaves(&leaves, format), format).unwrap();
+            assert_eq!(indices.into_iter().zip(values).collect::<Vec<_>>(), leaves);
+        }
+    }
+}